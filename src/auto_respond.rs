@@ -0,0 +1,219 @@
+//! Replies to plain messages that mention four or nubs.
+//!
+//! Off unless `AUTO_RESPOND=1` or `AUTO_RESPOND_GUILDS` is set, since it
+//! needs the privileged message content intent. Each guild then opts in or
+//! out with `/four-config auto-respond`, and guilds that haven't default to
+//! being in `AUTO_RESPOND_GUILDS`.
+
+use std::{ collections::HashSet, error::Error, sync::Arc, time::{ Duration, Instant } };
+
+use dashmap::DashMap;
+use twilight_http::{ request::channel::reaction::RequestReactionType, Client as HttpClient };
+use twilight_model::{
+    channel::Message,
+    id::{ marker::{ ChannelMarker, GuildMarker }, Id },
+};
+use crate::{ features, logging::Timed, media::nub_embed, rating::ContentRating, settings::GuildSettings, AppState };
+
+const FOUR_EMOJI: &str = "4️⃣";
+
+pub struct AutoResponder {
+    /// Guilds answered until they pick for themselves.
+    guilds: HashSet<Id<GuildMarker>>,
+    /// Channels the responder may post in. Empty means any channel.
+    channels: HashSet<Id<ChannelMarker>>,
+    /// Words answered with a four.
+    triggers: HashSet<String>,
+    /// Words that make the rest of the message a nub search.
+    nub_triggers: HashSet<String>,
+    /// React to four triggers instead of replying.
+    react: bool,
+    cooldown: Duration,
+    last_response: DashMap<Id<ChannelMarker>, Instant>,
+}
+
+impl AutoResponder {
+    pub fn from_env() -> Self {
+        let words = |name: &str, default: &[&str]| -> HashSet<String> {
+            let words = features::list(name);
+            if words.is_empty() {
+                default.iter().map(|word| word.to_string()).collect()
+            } else {
+                words.into_iter().map(|word| word.to_lowercase()).collect()
+            }
+        };
+
+        Self {
+            guilds: features::list("AUTO_RESPOND_GUILDS")
+                .iter()
+                .filter_map(|id| id.parse().ok())
+                .collect(),
+            channels: features::list("AUTO_RESPOND_CHANNELS")
+                .iter()
+                .filter_map(|id| id.parse().ok())
+                .collect(),
+            triggers: words("AUTO_RESPOND_TRIGGERS", &["four", "4"]),
+            nub_triggers: words("AUTO_RESPOND_NUB_TRIGGERS", &["nub"]),
+            react: dotenvy::var("AUTO_RESPOND_MODE").is_ok_and(|mode| mode == "react"),
+            cooldown: Duration::from_secs(
+                dotenvy::var("AUTO_RESPOND_COOLDOWN")
+                    .ok()
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(30)
+            ),
+            last_response: DashMap::new(),
+        }
+    }
+
+    fn allowed(&self, message: &Message, settings: &GuildSettings) -> bool {
+        !message.author.bot &&
+            message.guild_id.is_some_and(|id| settings.auto_respond.unwrap_or_else(|| self.guilds.contains(&id))) &&
+            (self.channels.is_empty() || self.channels.contains(&message.channel_id))
+    }
+
+    /// Claim the channel's cooldown, returning `false` if it hasn't passed yet.
+    fn cool_down(&self, channel_id: Id<ChannelMarker>) -> bool {
+        let now = Instant::now();
+        // forget channels whose cooldown is over, since they'd be ready anyway
+        self.last_response.retain(|_, last| now.duration_since(*last) < self.cooldown);
+
+        let mut ready = true;
+        self.last_response
+            .entry(channel_id)
+            .and_modify(|last| {
+                ready = now.duration_since(*last) >= self.cooldown;
+                if ready {
+                    *last = now;
+                }
+            })
+            .or_insert(now);
+        ready
+    }
+}
+
+pub async fn handle_message(
+    message: Message,
+    http: Arc<HttpClient>,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(responder) = &state.auto_responder else {
+        return Ok(());
+    };
+    let settings = message.guild_id
+        .and_then(|id| state.settings.get(&id.get()))
+        .unwrap_or_default();
+    if !responder.allowed(&message, &settings) || !settings.allows_channel(Some(message.channel_id)) {
+        return Ok(());
+    }
+    let nsfw = state.cache.channel(message.channel_id).and_then(|channel| channel.nsfw).unwrap_or(false);
+    let max_rating = ContentRating::allowed(settings.content_rating, message.guild_id.is_some(), nsfw);
+
+    let words: Vec<String> = message.content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    if words.iter().any(|word| responder.triggers.contains(word)) {
+        if !responder.cool_down(message.channel_id) {
+            return Ok(());
+        }
+
+        if responder.react {
+            http.create_reaction(
+                message.channel_id,
+                message.id,
+                &(RequestReactionType::Unicode { name: FOUR_EMOJI })
+            ).timed().await?;
+        } else {
+            let variant = state.variants.random(max_rating);
+            let mut reply = http.create_message(message.channel_id).reply(message.id);
+            if let Some(url) = &variant.url {
+                reply = reply.content(url);
+            }
+            reply.attachments(&Vec::from_iter(variant.attachment_file())).timed().await?;
+        }
+    } else if words.iter().any(|word| responder.nub_triggers.contains(word)) {
+        // the rest of the message is what they're looking for; only plain
+        // words go to the query parser so its syntax can't trip it up
+        let query = words
+            .iter()
+            .filter(|word| !responder.nub_triggers.contains(*word))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if query.is_empty() {
+            return Ok(());
+        }
+
+        let finder = state.clone();
+        let guild = message.guild_id.map(|id| id.get());
+        let results = tokio::task::spawn_blocking(move || {
+            finder.nub_finder.search(&query, guild, max_rating)
+        }).await??;
+        let Some((url, _)) = results.into_iter().next() else {
+            return Ok(());
+        };
+        if !responder.cool_down(message.channel_id) {
+            return Ok(());
+        }
+
+        let (embed, attachment) = nub_embed(&state, &url).await?;
+        http.create_message(message.channel_id)
+            .reply(message.id)
+            .embeds(&[embed])
+            .attachments(&Vec::from_iter(attachment))
+            .timed()
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{ message, GUILD_ID };
+
+    fn responder(guilds: &[&str]) -> AutoResponder {
+        AutoResponder {
+            guilds: guilds.iter().map(|id| id.parse().unwrap()).collect(),
+            channels: HashSet::new(),
+            triggers: HashSet::from(["four".to_string()]),
+            nub_triggers: HashSet::from(["nub".to_string()]),
+            react: false,
+            cooldown: Duration::from_secs(30),
+            last_response: DashMap::new(),
+        }
+    }
+
+    fn guild_message() -> Message {
+        let mut message = message("four");
+        message["guild_id"] = GUILD_ID.into();
+        serde_json::from_value(message).unwrap()
+    }
+
+    #[test]
+    fn guild_settings_override_the_default_guilds() {
+        let message = guild_message();
+        let on = GuildSettings { auto_respond: Some(true), ..Default::default() };
+        let off = GuildSettings { auto_respond: Some(false), ..Default::default() };
+
+        assert!(!responder(&[]).allowed(&message, &GuildSettings::default()));
+        assert!(responder(&[]).allowed(&message, &on));
+        assert!(responder(&[GUILD_ID]).allowed(&message, &GuildSettings::default()));
+        assert!(!responder(&[GUILD_ID]).allowed(&message, &off));
+    }
+
+    #[test]
+    fn cooldowns_are_forgotten_once_over() {
+        let responder = responder(&[]);
+        assert!(responder.cool_down(Id::new(1)));
+        assert!(!responder.cool_down(Id::new(1)));
+
+        responder.last_response.insert(Id::new(2), Instant::now() - responder.cooldown);
+        assert!(responder.cool_down(Id::new(3)));
+        assert!(!responder.last_response.contains_key(&Id::new(2)));
+        assert_eq!(responder.last_response.len(), 2);
+    }
+}
@@ -0,0 +1,180 @@
+//! Reroll and delete buttons attached to `/nub` replies.
+//!
+//! The favorite and tag buttons sit next to them but are handled in
+//! [`crate::favorites`] and [`crate::tags`].
+
+use std::{ error::Error, sync::Arc };
+
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    application::interaction::{ message_component::MessageComponentInteractionData, Interaction },
+    channel::message::{ component::{ ActionRow, Button, ButtonStyle }, Component, MessageFlags },
+    http::interaction::{ InteractionResponse, InteractionResponseType },
+    id::{ marker::UserMarker, Id },
+};
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::{ favorites::FAVORITE_ID, locale::Locale, logging::Timed, media::nub_embed, tags::EDIT_ID, AppState };
+
+pub const REROLL_ID: &str = "nub-reroll";
+pub const DELETE_ID: &str = "nub-delete";
+
+/// Discord caps `custom_id` at 100 characters.
+const CUSTOM_ID_LEN: usize = 100;
+
+/// Buttons for a `/nub` reply. The query is kept in the reroll `custom_id`,
+/// cut short if it doesn't fit.
+pub fn nub_buttons(query: &str, locale: Locale) -> Component {
+    let mut custom_id = format!("{}:{}", REROLL_ID, query);
+    if custom_id.len() > CUSTOM_ID_LEN {
+        let end = (0..=CUSTOM_ID_LEN).rev().find(|&i| custom_id.is_char_boundary(i)).unwrap_or(0);
+        custom_id.truncate(end);
+    }
+
+    Component::ActionRow(ActionRow {
+        components: vec![
+            Component::Button(Button {
+                custom_id: Some(custom_id),
+                disabled: false,
+                emoji: None,
+                label: Some(locale.text("reroll").to_string()),
+                style: ButtonStyle::Secondary,
+                url: None,
+                sku_id: None,
+            }),
+            Component::Button(Button {
+                custom_id: Some(FAVORITE_ID.to_string()),
+                disabled: false,
+                emoji: None,
+                label: Some(locale.text("⭐ favorite").to_string()),
+                style: ButtonStyle::Secondary,
+                url: None,
+                sku_id: None,
+            }),
+            Component::Button(Button {
+                custom_id: Some(EDIT_ID.to_string()),
+                disabled: false,
+                emoji: None,
+                label: Some(locale.text("🏷️ tags").to_string()),
+                style: ButtonStyle::Secondary,
+                url: None,
+                sku_id: None,
+            }),
+            Component::Button(Button {
+                custom_id: Some(DELETE_ID.to_string()),
+                disabled: false,
+                emoji: None,
+                label: Some(locale.text("delete").to_string()),
+                style: ButtonStyle::Danger,
+                url: None,
+                sku_id: None,
+            }),
+        ],
+    })
+}
+
+pub async fn handle_component(
+    interaction: &Interaction,
+    component: MessageComponentInteractionData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let client = http.interaction(interaction.application_id);
+
+    if invoker(interaction, &state) != interaction.author_id() {
+        client.create_response(
+            interaction.id,
+            &interaction.token,
+            &(InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(
+                    InteractionResponseDataBuilder::new()
+                        .content(Locale::of(interaction).text("that's not your nub :("))
+                        .flags(MessageFlags::EPHEMERAL)
+                        .build()
+                ),
+            })
+        ).timed().await?;
+        return Ok(());
+    }
+
+    let (action, query) = component.custom_id
+        .split_once(':')
+        .unwrap_or((component.custom_id.as_str(), ""));
+
+    match action {
+        REROLL_ID => {
+            // autocomplete picks carry a URL rather than something to search for
+            let query = Some(query)
+                .filter(|q| !q.is_empty() && !q.starts_with("nub:"))
+                .map(str::to_string);
+            let finder = state.clone();
+            let guild = interaction.guild_id.map(|id| id.get());
+            let max_rating = state.allowed_rating(interaction);
+            let nub = tokio::task::spawn_blocking(move || {
+                finder.nub_finder.random(query.as_deref(), guild, max_rating)
+            }).await??;
+            let Some((url, _)) = nub else {
+                client.create_response(
+                    interaction.id,
+                    &interaction.token,
+                    &(InteractionResponse {
+                        kind: InteractionResponseType::DeferredUpdateMessage,
+                        data: None,
+                    })
+                ).timed().await?;
+                return Ok(());
+            };
+            state.stats.record_pick(format!("nub:{}", url), interaction.guild_id);
+            let (embed, attachment) = nub_embed(&state, &url).await?;
+
+            client.create_response(
+                interaction.id,
+                &interaction.token,
+                &(InteractionResponse {
+                    kind: InteractionResponseType::UpdateMessage,
+                    data: Some(
+                        InteractionResponseDataBuilder::new()
+                            .embeds([embed])
+                            .attachments(attachment)
+                            .build()
+                    ),
+                })
+            ).timed().await?;
+        }
+
+        DELETE_ID => {
+            client.create_response(
+                interaction.id,
+                &interaction.token,
+                &(InteractionResponse {
+                    kind: InteractionResponseType::DeferredUpdateMessage,
+                    data: None,
+                })
+            ).timed().await?;
+            // for component interactions the original response is the message
+            // the button sits on
+            client.delete_response(&interaction.token).timed().await?;
+        }
+
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Who ran the command that produced the message the button is on.
+///
+/// Looked up in the cache first, falling back to the copy of the message
+/// Discord sends along with the interaction.
+fn invoker(interaction: &Interaction, state: &AppState) -> Option<Id<UserMarker>> {
+    let message = interaction.message.as_ref()?;
+
+    if let Some(cached) = state.cache.message(message.id)
+        && let Some(cached_interaction) = cached.interaction()
+    {
+        return Some(cached_interaction.user_id());
+    }
+
+    message.interaction_metadata.as_ref().map(|metadata| metadata.user.id)
+}
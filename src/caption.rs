@@ -0,0 +1,250 @@
+//! `/nub-caption`: top and bottom meme text drawn onto a nub.
+//!
+//! The text is drawn on every frame of GIFs, up to a limit. The bundled
+//! `caption.ttf` is DejaVu Sans Condensed Bold (license in
+//! `caption.ttf.LICENSE`); set `CAPTION_FONT` to a font file, like Impact, to
+//! use another one.
+
+use std::{ error::Error, fmt, io::Cursor, sync::Arc };
+
+use ab_glyph::{ FontArc, PxScale };
+use image::{
+    codecs::gif::{ GifDecoder, GifEncoder, Repeat },
+    AnimationDecoder,
+    ImageDecoder,
+    ImageFormat,
+    ImageReader,
+    Rgba,
+    RgbaImage,
+};
+use imageproc::drawing::{ draw_text_mut, text_size };
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::{
+    application::interaction::{ application_command::CommandData, Interaction },
+    channel::message::MessageFlags,
+    http::{
+        attachment::Attachment,
+        interaction::{ InteractionResponse, InteractionResponseType },
+    },
+};
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::{ find_nub, locale::Locale, logging::Timed, media, AppState };
+
+const CAPTION_TTF: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/caption.ttf"));
+
+/// Most GIF frames drawn on.
+const MAX_FRAMES: u64 = 300;
+
+/// Most pixels drawn on, across every frame.
+const MAX_PIXELS: u64 = 64_000_000;
+
+/// An image with more frames or pixels than [`edit_frames`] draws on.
+#[derive(Debug)]
+pub struct TooBig;
+
+impl fmt::Display for TooBig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "image has more than {} frames or {} pixels", MAX_FRAMES, MAX_PIXELS)
+    }
+}
+
+impl Error for TooBig {}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "nub-caption", desc = "put meme text on a nub", contexts = "guild bot_dm private_channel")]
+pub struct NubCaptionCommand {
+    #[command(desc = "the nub cat to caption", autocomplete = true)]
+    nub: String,
+
+    #[command(desc = "text along the top", max_length = 100)]
+    top: Option<String>,
+
+    #[command(desc = "text along the bottom", max_length = 100)]
+    bottom: Option<String>,
+}
+
+pub async fn handle_command(
+    interaction: &Interaction,
+    cmd: CommandData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let caption = match NubCaptionCommand::from_interaction(cmd.into()) {
+        Ok(o) => o,
+        Err(e) => {
+            tracing::error!(?e);
+            return Err(e.into());
+        }
+    };
+    let client = http.interaction(interaction.application_id);
+    let locale = Locale::of(interaction);
+
+    if caption.top.is_none() && caption.bottom.is_none() {
+        client.create_response(
+            interaction.id,
+            &interaction.token,
+            &(InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(
+                    InteractionResponseDataBuilder::new()
+                        .content(locale.text("give me some text to put on it"))
+                        .flags(MessageFlags::EPHEMERAL)
+                        .build()
+                ),
+            })
+        ).timed().await?;
+        return Ok(());
+    }
+
+    // GIFs take a while to redraw
+    client.create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::DeferredChannelMessageWithSource,
+            data: None,
+        })
+    ).timed().await?;
+
+    let Some(url) = find_nub(state.clone(), caption.nub, interaction.guild_id, state.allowed_rating(interaction)).await? else {
+        client.update_response(&interaction.token).content(Some(locale.text("i couldn't find that nub :("))).timed().await?;
+        return Ok(());
+    };
+    let bytes = match media::nub_bytes(&state, &url).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!(url, ?e, "couldn't download nub to caption");
+            client.update_response(&interaction.token).content(Some(locale.text("i couldn't download that nub :("))).timed().await?;
+            return Ok(());
+        }
+    };
+    state.stats.record_pick(format!("nub:{}", url), interaction.guild_id);
+
+    let (top, bottom) = (caption.top, caption.bottom);
+    let rendered = tokio::task::spawn_blocking(move || {
+        render(&bytes, top.as_deref(), bottom.as_deref())
+    }).await?;
+
+    match rendered {
+        Ok((filename, rendered)) => {
+            client
+                .update_response(&interaction.token)
+                .attachments(&[Attachment::from_bytes(filename, rendered, 1)])
+                .timed().await?;
+        }
+        Err(e) => {
+            tracing::warn!(url, ?e, "couldn't caption nub");
+            client.update_response(&interaction.token).content(Some(locale.text(drawing_error(&*e)))).timed().await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What to tell the user when [`edit_frames`] fails.
+pub fn drawing_error(e: &(dyn Error + Send + Sync + 'static)) -> &'static str {
+    if e.is::<TooBig>() { "that's too big for me to draw on :(" } else { "i couldn't draw on that :(" }
+}
+
+/// Draw the caption on an image, or every frame of a GIF.
+///
+/// Returns: `(filename, bytes)`
+fn render(
+    bytes: &[u8],
+    top: Option<&str>,
+    bottom: Option<&str>
+) -> Result<(String, Vec<u8>), Box<dyn Error + Send + Sync>> {
+    let font = font()?;
+    edit_frames(bytes, "nub", |image| draw_caption(image, &font, top, bottom))
+}
+
+/// Run `edit` on an image, or every frame of a GIF, and encode the result as a
+/// PNG or GIF named after `name`. Fails with [`TooBig`] before decoding
+/// anything past the limits.
+///
+/// Returns: `(filename, bytes)`
+pub fn edit_frames(
+    bytes: &[u8],
+    name: &str,
+    mut edit: impl FnMut(&mut RgbaImage)
+) -> Result<(String, Vec<u8>), Box<dyn Error + Send + Sync>> {
+    if image::guess_format(bytes)? == ImageFormat::Gif {
+        let decoder = GifDecoder::new(Cursor::new(bytes))?;
+        let max_frames = max_frames(decoder.dimensions())?;
+        let frames = decoder.into_frames().take(max_frames as usize + 1).collect::<Result<Vec<_>, _>>()?;
+        if frames.len() as u64 > max_frames {
+            return Err(TooBig.into());
+        }
+        let mut out = Vec::new();
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut out, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            for mut frame in frames {
+                edit(frame.buffer_mut());
+                encoder.encode_frame(frame)?;
+            }
+        }
+        return Ok((format!("{}.gif", name), out));
+    }
+
+    max_frames(ImageReader::new(Cursor::new(bytes)).with_guessed_format()?.into_dimensions()?)?;
+    let mut image = image::load_from_memory(bytes)?.into_rgba8();
+    edit(&mut image);
+    let mut out = Vec::new();
+    image.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)?;
+    Ok((format!("{}.png", name), out))
+}
+
+/// How many frames of this size fit in the limits.
+fn max_frames((width, height): (u32, u32)) -> Result<u64, TooBig> {
+    let pixels = (width as u64 * height as u64).max(1);
+    match (MAX_PIXELS / pixels).min(MAX_FRAMES) {
+        0 => Err(TooBig),
+        frames => Ok(frames),
+    }
+}
+
+fn font() -> Result<FontArc, Box<dyn Error + Send + Sync>> {
+    match dotenvy::var("CAPTION_FONT") {
+        Ok(path) => Ok(FontArc::try_from_vec(std::fs::read(path)?)?),
+        Err(_) => Ok(FontArc::try_from_slice(CAPTION_TTF)?),
+    }
+}
+
+fn draw_caption(image: &mut RgbaImage, font: &FontArc, top: Option<&str>, bottom: Option<&str>) {
+    if let Some(top) = top {
+        draw_line(image, font, top, true);
+    }
+    if let Some(bottom) = bottom {
+        draw_line(image, font, bottom, false);
+    }
+}
+
+/// White uppercase text with a black outline, shrunk until it fits the width.
+fn draw_line(image: &mut RgbaImage, font: &FontArc, text: &str, top: bool) {
+    let text = text.trim().to_uppercase();
+    let (width, height) = image.dimensions();
+    let margin = (height / 30).max(2);
+
+    let mut scale = height as f32 / 7.0;
+    let (mut text_width, mut text_height) = text_size(PxScale::from(scale), font, &text);
+    while text_width > width.saturating_sub(margin * 2) && scale > 8.0 {
+        scale *= 0.9;
+        (text_width, text_height) = text_size(PxScale::from(scale), font, &text);
+    }
+
+    let x = (width.saturating_sub(text_width) / 2) as i32;
+    let y = if top { margin } else { height.saturating_sub(text_height + margin * 2) } as i32;
+    let outline = ((scale / 16.0) as i32).max(1);
+
+    for dx in -outline..=outline {
+        for dy in -outline..=outline {
+            if dx != 0 || dy != 0 {
+                draw_text_mut(image, Rgba([0, 0, 0, 255]), x + dx, y + dy, scale, font, &text);
+            }
+        }
+    }
+    draw_text_mut(image, Rgba([255, 255, 255, 255]), x, y, scale, font, &text);
+}
@@ -0,0 +1,343 @@
+//! Command-line subcommands for running and maintaining the bot.
+//!
+//! Everything other than `run` works without a gateway connection, and
+//! `register-commands --dry-run`, `catalog`, `search` and `replay` don't need
+//! a token.
+
+use std::{ error::Error, fs::File, io::{ self, BufReader, Write }, path::{ Path, PathBuf }, sync::Arc };
+
+use clap::{ Parser, Subcommand, ValueEnum };
+use reqwest::Url;
+use twilight_cache_inmemory::DefaultInMemoryCache;
+use twilight_http::Client as HttpClient;
+use twilight_model::id::{ marker::GuildMarker, Id };
+
+use crate::{
+    commands,
+    config::Config,
+    dedup,
+    mock::MockDiscord,
+    nub::{ fetch_nubs, get_nubs, load_nubs, save_nubs, split_keywords, Nub, NubFinder },
+    rating::ContentRating,
+    recorder,
+    variants::Variants,
+    AppState,
+};
+
+#[derive(Parser)]
+#[command(name = "four", about = "the four discord bot")]
+pub struct Cli {
+    /// What to do, `run` by default.
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    /// Connect to Discord and answer commands.
+    Run,
+
+    /// Register the slash and context menu commands without connecting.
+    RegisterCommands {
+        /// Register in one server only, where changes show up immediately.
+        #[arg(long, conflicts_with = "global", required_unless_present_any = ["global", "dry_run"])]
+        guild: Option<Id<GuildMarker>>,
+
+        /// Register everywhere, like `run` does on startup.
+        #[arg(long)]
+        global: bool,
+
+        /// Print the commands as JSON instead of registering them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Manage the nub catalog file.
+    #[command(subcommand)]
+    Catalog(CatalogCommand),
+
+    /// Search the catalog like `/nub` autocomplete does.
+    Search {
+        query: String,
+
+        /// Include the nubs added to this server.
+        #[arg(long)]
+        guild: Option<u64>,
+
+        /// The edgiest nubs to include.
+        #[arg(long, value_parser = parse_rating, default_value = "edgy")]
+        rating: ContentRating,
+    },
+
+    /// Run the interactions in a recording against a mock Discord and print
+    /// the responses, see `RECORD_INTERACTIONS`.
+    Replay {
+        path: PathBuf,
+
+        /// Settings, favorites and the rest are read from and saved to here.
+        /// A temporary directory by default, so nothing real changes.
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CatalogCommand {
+    /// Download the catalog again, replacing the saved one.
+    Fetch,
+
+    /// Write the saved catalog out.
+    Export {
+        #[arg(long, value_enum, default_value_t = CatalogFormat::Json)]
+        format: CatalogFormat,
+
+        /// Where to write it, standard output by default.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Replace the saved catalog with a file written by `export`.
+    Import {
+        path: PathBuf,
+
+        /// Guessed from the file extension when not given.
+        #[arg(long, value_enum)]
+        format: Option<CatalogFormat>,
+    },
+
+    /// Merge nubs that are the same image under different URLs.
+    Dedup,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CatalogFormat {
+    Json,
+    /// `source,tags` rows, with the tags separated by commas.
+    Csv,
+}
+
+/// A catalog row in CSV form.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CsvNub {
+    source: String,
+    tags: String,
+}
+
+fn parse_rating(value: &str) -> Result<ContentRating, String> {
+    ContentRating::parse(value).ok_or_else(|| "expected safe, edgy or nsfw".to_string())
+}
+
+pub async fn register_commands(
+    guild: Option<Id<GuildMarker>>,
+    global: bool,
+    dry_run: bool
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let commands = commands(&Variants::load()?);
+    if dry_run {
+        println!("{}", serde_json::to_string_pretty(&commands)?);
+        return Ok(());
+    }
+
+    let http = HttpClient::new(dotenvy::var("DISCORD_TOKEN")?);
+    let application = http.current_user_application().await?.model().await?;
+    let client = http.interaction(application.id);
+    match (guild, global) {
+        (Some(guild), false) => client.set_guild_commands(guild, &commands).await?,
+        (None, true) => client.set_global_commands(&commands).await?,
+        _ => return Err("register the commands with either --guild or --global".into()),
+    };
+
+    tracing::info!(count = commands.len(), ?guild, "registered commands");
+    Ok(())
+}
+
+pub async fn catalog(command: CatalogCommand, config: &Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let path = &config.catalog.path;
+    match command {
+        CatalogCommand::Fetch => {
+            let nubs = fetch_nubs(&config.catalog.endpoint).await?;
+            save_nubs(path, &nubs)?;
+            tracing::info!(count = nubs.len(), path = %path.display(), "fetched the catalog");
+        }
+
+        CatalogCommand::Export { format, output } => {
+            let nubs = load_nubs(path)?;
+            match output {
+                Some(output) => export(&nubs, format, File::create(output)?)?,
+                None => export(&nubs, format, io::stdout().lock())?,
+            }
+        }
+
+        CatalogCommand::Import { path: input, format } => {
+            let nubs = import(&input, format)?;
+            save_nubs(path, &nubs)?;
+            tracing::info!(count = nubs.len(), path = %path.display(), "imported the catalog");
+        }
+
+        CatalogCommand::Dedup => {
+            dedup::run(config).await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn search(
+    query: &str,
+    guild: Option<u64>,
+    rating: ContentRating,
+    config: &Config
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let finder = NubFinder::new(config)?;
+    finder.commit(get_nubs(config).await?)?;
+
+    let results = finder.search(query, guild, rating)?;
+    if results.is_empty() {
+        eprintln!("no nubs found");
+    }
+    for (url, keywords) in results {
+        println!("{}\t{}", keywords, url);
+    }
+
+    Ok(())
+}
+
+/// A directory removed when this is dropped, however the command ends.
+struct TemporaryDir(PathBuf);
+
+impl Drop for TemporaryDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            tracing::warn!(dir = %self.0.display(), ?e, "couldn't remove temporary directory");
+        }
+    }
+}
+
+pub async fn replay(
+    path: &Path,
+    data_dir: Option<PathBuf>,
+    mut config: Config
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let _temporary = match data_dir {
+        Some(data_dir) => {
+            config.storage.dir = data_dir;
+            None
+        }
+        None => {
+            config.storage.dir = std::env::temp_dir().join(format!("four-replay-{}", std::process::id()));
+            Some(TemporaryDir(config.storage.dir.clone()))
+        }
+    };
+    std::fs::create_dir_all(&config.storage.dir)?;
+
+    let nubs = get_nubs(&config).await?;
+    let state = Arc::new(AppState::new(config, DefaultInMemoryCache::new(), None, None, Variants::load()?)?);
+    state.nub_finder.commit(nubs)?;
+
+    let discord = MockDiscord::start().await?;
+    let replayed = recorder::replay(BufReader::new(File::open(path)?), &discord, state).await?;
+
+    let mut out = io::stdout().lock();
+    for entry in replayed {
+        serde_json::to_writer(&mut out, &entry)?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn export(nubs: &[Nub], format: CatalogFormat, mut out: impl Write) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match format {
+        CatalogFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, nubs)?;
+            writeln!(out)?;
+        }
+        CatalogFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for nub in nubs {
+                writer.serialize(CsvNub { source: nub.source.to_string(), tags: nub.tags.join(", ") })?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a catalog, checking every nub has a URL. Nubs without tags are kept,
+/// the way the catalog has them, and just can't be searched for.
+fn import(path: &Path, format: Option<CatalogFormat>) -> Result<Vec<Nub>, Box<dyn Error + Send + Sync>> {
+    let format = format.unwrap_or(match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => CatalogFormat::Csv,
+        _ => CatalogFormat::Json,
+    });
+
+    let nubs = match format {
+        CatalogFormat::Json => serde_json::from_reader::<_, Vec<Nub>>(File::open(path)?)?,
+        CatalogFormat::Csv => csv::Reader::from_path(path)?
+            .deserialize::<CsvNub>()
+            .map(|row| {
+                let row = row?;
+                Ok(Nub {
+                    source: row.source.into(),
+                    tags: split_keywords(&row.tags).into_iter().map(Into::into).collect(),
+                })
+            })
+            .collect::<Result<_, Box<dyn Error + Send + Sync>>>()?,
+    };
+
+    for (i, nub) in nubs.iter().enumerate() {
+        if let Err(e) = Url::parse(&nub.source) {
+            return Err(format!("nub {} has an invalid source `{}`: {}", i + 1, nub.source, e).into());
+        }
+    }
+
+    Ok(nubs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn catalog() -> Vec<Nub> {
+        vec![
+            Nub { source: "https://example.com/a.gif".into(), tags: vec!["sleepy".into(), "bed".into()] },
+            Nub { source: "https://example.com/b.gif?x=1,2".into(), tags: vec!["café".into(), "\"quoted\"".into()] },
+            Nub { source: "https://example.com/c.gif".into(), tags: Vec::new() },
+        ]
+    }
+
+    fn round_trip(format: CatalogFormat, filename: &str) -> Vec<Nub> {
+        let dir = TempDir::new();
+        let path = dir.path().join(filename);
+        export(&catalog(), format, File::create(&path).unwrap()).unwrap();
+        import(&path, None).unwrap()
+    }
+
+    fn sources_and_tags(nubs: &[Nub]) -> Vec<(String, Vec<String>)> {
+        nubs.iter().map(|nub| (nub.source.to_string(), nub.tags.iter().map(|tag| tag.to_string()).collect())).collect()
+    }
+
+    #[test]
+    fn catalogs_round_trip_as_json() {
+        assert_eq!(sources_and_tags(&round_trip(CatalogFormat::Json, "catalog.json")), sources_and_tags(&catalog()));
+    }
+
+    #[test]
+    fn catalogs_round_trip_as_csv() {
+        assert_eq!(sources_and_tags(&round_trip(CatalogFormat::Csv, "catalog.csv")), sources_and_tags(&catalog()));
+    }
+
+    #[test]
+    fn import_checks_every_nub() {
+        let dir = TempDir::new();
+        let path = dir.path().join("catalog.csv");
+        std::fs::write(&path, "source,tags\nnot a url,cat\n").unwrap();
+        assert!(import(&path, None).is_err());
+
+        std::fs::write(&path, "source,tags\nhttps://example.com/a.gif,\n").unwrap();
+        let nubs = import(&path, Some(CatalogFormat::Csv)).unwrap();
+        assert!(nubs[0].tags.is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+//! Settings read once at startup.
+//!
+//! They come from `four.toml`, or the file `FOUR_CONFIG` points to, and each
+//! one can be overridden by an environment variable. Anything left out keeps
+//! its default, so neither the file nor the variables are needed. Feature
+//! toggles stay in the environment, see [`crate::features`].
+
+use std::{ error::Error, net::SocketAddr, path::PathBuf, str::FromStr };
+
+use reqwest::Url;
+
+const DEFAULT_PATH: &str = "four.toml";
+
+/// The least memory tantivy will index with, in bytes.
+const MIN_WRITER_HEAP: usize = 15_000_000;
+
+/// Discord allows this many autocomplete choices.
+const MAX_CHOICES: usize = 25;
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub catalog: CatalogConfig,
+    pub search: SearchConfig,
+    pub storage: StorageConfig,
+    pub server: ServerConfig,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogConfig {
+    /// Where the nub catalog is downloaded from (`NUB_ENDPOINT`).
+    pub endpoint: String,
+    /// The downloaded catalog, fetched again only when missing (`NUB_CATALOG`).
+    pub path: PathBuf,
+    /// The user who can edit the tags and ratings of catalog nubs
+    /// (`CATALOG_OWNER`), required to run the bot. Moderators can only edit
+    /// their server's.
+    pub owner: Option<u64>,
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://solanapulseserver-production.up.railway.app/memeslist".to_string(),
+            path: "four.bin".into(),
+            owner: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Memory for building the search index, in bytes (`INDEX_WRITER_HEAP`).
+    pub writer_heap: usize,
+    /// Most nubs a search returns (`SEARCH_LIMIT`).
+    pub limit: usize,
+    /// Most nubs offered while typing, up to 25 (`AUTOCOMPLETE_LIMIT`).
+    pub autocomplete_limit: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            writer_heap: 100_000_000,
+            limit: 10,
+            autocomplete_limit: MAX_CHOICES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Where settings, stats, favorites and the rest are saved (`DATA_DIR`).
+    pub dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self { dir: ".".into() }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Where to serve `/metrics`, `/healthz` and `/readyz`, off when unset
+    /// (`SERVER_ADDR`).
+    pub addr: Option<SocketAddr>,
+}
+
+impl StorageConfig {
+    /// The path of a store file.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Config {
+    /// Read the config file, apply the environment on top and check the result.
+    pub fn load() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let path = match dotenvy::var("FOUR_CONFIG") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.exists()),
+        };
+        let mut config = match path {
+            Some(path) => toml::from_str(&std::fs::read_to_string(&path)?)
+                .map_err(|e| format!("invalid config in {}: {}", path.display(), e))?,
+            None => Self::default(),
+        };
+
+        env("NUB_ENDPOINT", &mut config.catalog.endpoint)?;
+        env("NUB_CATALOG", &mut config.catalog.path)?;
+        optional_env("CATALOG_OWNER", &mut config.catalog.owner)?;
+        env("INDEX_WRITER_HEAP", &mut config.search.writer_heap)?;
+        env("SEARCH_LIMIT", &mut config.search.limit)?;
+        env("AUTOCOMPLETE_LIMIT", &mut config.search.autocomplete_limit)?;
+        env("DATA_DIR", &mut config.storage.dir)?;
+        optional_env("SERVER_ADDR", &mut config.server.addr)?;
+
+        config.validate()?;
+        std::fs::create_dir_all(&config.storage.dir)?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Err(e) = Url::parse(&self.catalog.endpoint) {
+            return Err(format!("catalog.endpoint `{}` isn't a URL: {}", self.catalog.endpoint, e).into());
+        }
+        if self.search.writer_heap < MIN_WRITER_HEAP {
+            return Err(format!("search.writer_heap must be at least {} bytes", MIN_WRITER_HEAP).into());
+        }
+        if self.search.limit == 0 {
+            return Err("search.limit must be at least 1".into());
+        }
+        if !(1..=MAX_CHOICES).contains(&self.search.autocomplete_limit) {
+            return Err(format!("search.autocomplete_limit must be 1 to {}", MAX_CHOICES).into());
+        }
+        if self.storage.dir.is_file() {
+            return Err(format!("storage.dir {} is a file", self.storage.dir.display()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Replace `value` with the variable `name`, if it's set.
+fn env<T: FromStr>(name: &str, value: &mut T) -> Result<(), Box<dyn Error + Send + Sync>>
+    where T::Err: std::fmt::Display
+{
+    if let Ok(raw) = dotenvy::var(name) {
+        *value = raw.parse().map_err(|e| format!("invalid {}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Like [`env`], where setting the variable to nothing turns the setting off.
+fn optional_env<T: FromStr>(name: &str, value: &mut Option<T>) -> Result<(), Box<dyn Error + Send + Sync>>
+    where T::Err: std::fmt::Display
+{
+    if let Ok(raw) = dotenvy::var(name) {
+        *value = match raw.trim() {
+            "" => None,
+            raw => Some(raw.parse().map_err(|e| format!("invalid {}: {}", name, e))?),
+        };
+    }
+    Ok(())
+}
@@ -0,0 +1,514 @@
+//! Context-menu commands (right-click a message or user → Apps).
+//!
+//! Context menus can't take options, so picking what to reply with happens in
+//! an ephemeral follow-up: select menus and buttons for fixed choices and a
+//! modal for nub queries. The target travels along in the component
+//! `custom_id` as `<action>:<channel id>:<message id>` for messages and
+//! `<action>:<user id>:<ping>` for users.
+
+use std::{ error::Error, sync::Arc };
+
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandOption, CreateCommand };
+use twilight_model::{
+    application::{
+        command::{ Command, CommandOptionChoiceValue, CommandType },
+        interaction::{
+            application_command::{ CommandData, CommandOptionValue },
+            message_component::MessageComponentInteractionData,
+            modal::ModalInteractionData,
+            Interaction,
+            InteractionContextType,
+        },
+    },
+    channel::message::{
+        component::{
+            ActionRow,
+            Button,
+            ButtonStyle,
+            SelectMenu,
+            SelectMenuOption,
+            SelectMenuType,
+            TextInput,
+            TextInputStyle,
+        },
+        AllowedMentions,
+        Component,
+        Message,
+        MessageFlags,
+    },
+    http::interaction::{ InteractionResponse, InteractionResponseData, InteractionResponseType },
+    id::{ marker::{ ChannelMarker, GuildMarker, MessageMarker, UserMarker }, Id },
+};
+use twilight_util::builder::{
+    command::CommandBuilder,
+    InteractionResponseDataBuilder,
+};
+
+use crate::{
+    find_nub,
+    locale::Locale,
+    logging::Timed,
+    media::nub_embed,
+    AppState,
+    FourCommand,
+    RagebaitRating,
+    RateRagebaitCommand,
+};
+
+const RATE_RAGEBAIT: &str = "Rate ragebait";
+const REPLY_WITH_FOUR: &str = "Reply with four";
+const NUB_THIS_USER: &str = "Nub this user";
+
+/// Select menu choosing a [`RagebaitRating`].
+const RATE_RAGEBAIT_ID: &str = "rate-ragebait";
+/// Select menu choosing a [`crate::variants::Variant`].
+const REPLY_FOUR_ID: &str = "reply-four";
+/// Button opening, and modal searching for, a nub.
+const REPLY_NUB_ID: &str = "reply-nub";
+/// Button sending a random nub at a user.
+const NUB_USER_RANDOM_ID: &str = "nub-user-random";
+/// Button opening, and modal searching for, a themed nub for a user.
+const NUB_USER_THEMED_ID: &str = "nub-user-themed";
+/// Button toggling whether the user gets pinged.
+const NUB_USER_PING_ID: &str = "nub-user-ping";
+/// Text input inside the nub modals.
+const NUB_QUERY_ID: &str = "query";
+
+pub fn rate_ragebait_command() -> Command {
+    CommandBuilder::new(RATE_RAGEBAIT, "", CommandType::Message)
+        .contexts([
+            InteractionContextType::Guild,
+            InteractionContextType::BotDm,
+            InteractionContextType::PrivateChannel,
+        ])
+        .build()
+}
+
+pub fn reply_with_four_command() -> Command {
+    CommandBuilder::new(REPLY_WITH_FOUR, "", CommandType::Message)
+        .contexts([
+            InteractionContextType::Guild,
+            InteractionContextType::BotDm,
+            InteractionContextType::PrivateChannel,
+        ])
+        .build()
+}
+
+pub fn nub_this_user_command() -> Command {
+    CommandBuilder::new(NUB_THIS_USER, "", CommandType::User)
+        .contexts([
+            InteractionContextType::Guild,
+            InteractionContextType::BotDm,
+            InteractionContextType::PrivateChannel,
+        ])
+        .build()
+}
+
+/// Handle a context-menu command by asking what to reply with.
+pub async fn handle_command(
+    interaction: &Interaction,
+    cmd: CommandData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let locale = Locale::of(interaction);
+    let components = match cmd.name.as_ref() {
+        RATE_RAGEBAIT => {
+            let Some(message) = resolved_message(&cmd) else {
+                return Ok(());
+            };
+            vec![
+                select_menu(
+                    format!("{}:{}:{}", RATE_RAGEBAIT_ID, message.channel_id, message.id),
+                    locale.text("how'd you rate ts?"),
+                    RateRagebaitCommand::create_command().into(),
+                    locale
+                ),
+            ]
+        }
+
+        REPLY_WITH_FOUR => {
+            let Some(message) = resolved_message(&cmd) else {
+                return Ok(());
+            };
+            let target = format!("{}:{}", message.channel_id, message.id);
+            let mut four = FourCommand::create_command().into();
+            state.variants.fill_choices(&mut four, None, "variant", state.allowed_rating(interaction));
+            vec![
+                select_menu(
+                    format!("{}:{}", REPLY_FOUR_ID, target),
+                    locale.text("pick a four"),
+                    four,
+                    locale
+                ),
+                Component::ActionRow(ActionRow {
+                    components: vec![
+                        button(format!("{}:{}", REPLY_NUB_ID, target), locale.text("or find a nub...")),
+                    ],
+                }),
+            ]
+        }
+
+        NUB_THIS_USER => {
+            let Some(user_id) = cmd.target_id else {
+                return Ok(());
+            };
+            nub_user_components(user_id.cast(), true, locale)
+        }
+
+        _ => {
+            return Ok(());
+        }
+    };
+
+    http.interaction(interaction.application_id).create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(
+                InteractionResponseDataBuilder::new()
+                    .components(components)
+                    .flags(MessageFlags::EPHEMERAL)
+                    .build()
+            ),
+        })
+    ).timed().await?;
+
+    Ok(())
+}
+
+/// Handle a pick from one of the context-menu follow-ups.
+pub async fn handle_component(
+    interaction: &Interaction,
+    component: MessageComponentInteractionData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some((action, target)) = component.custom_id.split_once(':') else {
+        return Ok(());
+    };
+    let locale = Locale::of(interaction);
+
+    let (kind, data) = match action {
+        RATE_RAGEBAIT_ID => {
+            let Some((channel_id, message_id)) = parse_message_target(target) else {
+                return Ok(());
+            };
+            let Some(rating) = component.values
+                .into_iter()
+                .next()
+                .and_then(parse_choice::<RagebaitRating>)
+            else {
+                return Ok(());
+            };
+            state.stats.record_pick(format!("rate-ragebait:{}", rating.value()), interaction.guild_id);
+            let reply = reply_line(interaction.guild_id, channel_id, message_id);
+            (
+                InteractionResponseType::ChannelMessageWithSource,
+                InteractionResponseDataBuilder::new()
+                    .content(format!("{}\n{}", reply, rating.url()))
+                    .build(),
+            )
+        }
+
+        REPLY_FOUR_ID => {
+            let Some((channel_id, message_id)) = parse_message_target(target) else {
+                return Ok(());
+            };
+            let Some(variant) = component.values
+                .first()
+                .and_then(|value| state.variants.get(value))
+                .filter(|variant| variant.rating <= state.allowed_rating(interaction))
+            else {
+                return Ok(());
+            };
+            state.stats.record_pick(format!("four:{}", variant.value), interaction.guild_id);
+            let reply = reply_line(interaction.guild_id, channel_id, message_id);
+            (
+                InteractionResponseType::ChannelMessageWithSource,
+                variant.response(Some(&reply)),
+            )
+        }
+
+        REPLY_NUB_ID => {
+            (InteractionResponseType::Modal, nub_modal(&component.custom_id, locale.text("find a nub"), locale))
+        }
+
+        NUB_USER_RANDOM_ID => {
+            let Some((user_id, ping)) = parse_user_target(target) else {
+                return Ok(());
+            };
+            let finder = state.clone();
+            let guild = interaction.guild_id.map(|id| id.get());
+            let max_rating = state.allowed_rating(interaction);
+            let nub = tokio::task::spawn_blocking(move || {
+                finder.nub_finder.random(None, guild, max_rating)
+            }).await??;
+            (
+                InteractionResponseType::ChannelMessageWithSource,
+                nub_user_response(&state, interaction.guild_id, nub, user_id, ping, locale).await?,
+            )
+        }
+
+        NUB_USER_THEMED_ID => {
+            (InteractionResponseType::Modal, nub_modal(&component.custom_id, locale.text("pick a theme"), locale))
+        }
+
+        NUB_USER_PING_ID => {
+            let Some((user_id, ping)) = parse_user_target(target) else {
+                return Ok(());
+            };
+            (
+                InteractionResponseType::UpdateMessage,
+                InteractionResponseDataBuilder::new()
+                    .components(nub_user_components(user_id, !ping, locale))
+                    .build(),
+            )
+        }
+
+        _ => {
+            return Ok(());
+        }
+    };
+
+    http.interaction(interaction.application_id).create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse { kind, data: Some(data) })
+    ).timed().await?;
+
+    Ok(())
+}
+
+/// Handle the nub search modals.
+pub async fn handle_modal(
+    interaction: &Interaction,
+    modal: ModalInteractionData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some((action, target)) = modal.custom_id.split_once(':') else {
+        return Ok(());
+    };
+    let Some(query) = modal.components
+        .into_iter()
+        .flat_map(|row| row.components)
+        .find(|input| input.custom_id == NUB_QUERY_ID)
+        .and_then(|input| input.value)
+    else {
+        return Ok(());
+    };
+
+    let locale = Locale::of(interaction);
+    let data = match action {
+        REPLY_NUB_ID => {
+            let Some((channel_id, message_id)) = parse_message_target(target) else {
+                return Ok(());
+            };
+            match find_nub(state.clone(), query, interaction.guild_id, state.allowed_rating(interaction)).await? {
+                Some(url) => {
+                    state.stats.record_pick(format!("nub:{}", url), interaction.guild_id);
+                    let (embed, attachment) = nub_embed(&state, &url).await?;
+                    InteractionResponseDataBuilder::new()
+                        .content(reply_line(interaction.guild_id, channel_id, message_id))
+                        .embeds([embed])
+                        .attachments(attachment)
+                        .build()
+                }
+                None => not_found(locale),
+            }
+        }
+
+        NUB_USER_THEMED_ID => {
+            let Some((user_id, ping)) = parse_user_target(target) else {
+                return Ok(());
+            };
+            let finder = state.clone();
+            let guild = interaction.guild_id.map(|id| id.get());
+            let max_rating = state.allowed_rating(interaction);
+            let nub = tokio::task::spawn_blocking(move || {
+                finder.nub_finder.random(Some(&query), guild, max_rating)
+            }).await??;
+            nub_user_response(&state, interaction.guild_id, nub, user_id, ping, locale).await?
+        }
+
+        _ => {
+            return Ok(());
+        }
+    };
+
+    http.interaction(interaction.application_id).create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(data),
+        })
+    ).timed().await?;
+
+    Ok(())
+}
+
+fn resolved_message(cmd: &CommandData) -> Option<&Message> {
+    let message = cmd.target_id.and_then(|id| cmd.resolved.as_ref()?.messages.get(&id.cast()));
+    if message.is_none() {
+        tracing::warn!(name = cmd.name, "message command without a resolved target");
+    }
+    message
+}
+
+/// The follow-up for "Nub this user", with a toggle for pinging them.
+fn nub_user_components(user_id: Id<UserMarker>, ping: bool, locale: Locale) -> Vec<Component> {
+    let target = format!("{}:{}", user_id, u8::from(ping));
+    vec![
+        Component::ActionRow(ActionRow {
+            components: vec![
+                button(format!("{}:{}", NUB_USER_RANDOM_ID, target), locale.text("random nub")),
+                button(format!("{}:{}", NUB_USER_THEMED_ID, target), locale.text("pick a theme...")),
+                button(
+                    format!("{}:{}", NUB_USER_PING_ID, target),
+                    locale.text(if ping { "ping: on" } else { "ping: off" })
+                ),
+            ],
+        }),
+    ]
+}
+
+/// Mention the user alongside the nub, only letting the mention through if
+/// the invoker asked for a ping.
+async fn nub_user_response(
+    state: &AppState,
+    guild_id: Option<Id<GuildMarker>>,
+    nub: Option<(String, String)>,
+    user_id: Id<UserMarker>,
+    ping: bool,
+    locale: Locale
+) -> Result<InteractionResponseData, Box<dyn Error + Send + Sync>> {
+    let Some((url, _)) = nub else {
+        return Ok(not_found(locale));
+    };
+    state.stats.record_pick(format!("nub:{}", url), guild_id);
+    let (embed, attachment) = nub_embed(state, &url).await?;
+
+    Ok(
+        InteractionResponseDataBuilder::new()
+            .content(format!("<@{}>", user_id))
+            .allowed_mentions(AllowedMentions {
+                users: if ping { vec![user_id] } else { vec![] },
+                ..Default::default()
+            })
+            .embeds([embed])
+            .attachments(attachment)
+            .build()
+    )
+}
+
+fn not_found(locale: Locale) -> InteractionResponseData {
+    InteractionResponseDataBuilder::new()
+        .content(locale.text("i couldn't find that nub :("))
+        .flags(MessageFlags::EPHEMERAL)
+        .build()
+}
+
+fn nub_modal(custom_id: &str, title: &str, locale: Locale) -> InteractionResponseData {
+    InteractionResponseDataBuilder::new()
+        .custom_id(custom_id)
+        .title(title)
+        .components([
+            Component::ActionRow(ActionRow {
+                components: vec![
+                    Component::TextInput(TextInput {
+                        custom_id: NUB_QUERY_ID.to_string(),
+                        label: locale.text("the nub cat you're looking for").to_string(),
+                        max_length: Some(100),
+                        min_length: Some(1),
+                        placeholder: None,
+                        required: Some(true),
+                        style: TextInputStyle::Short,
+                        value: None,
+                    }),
+                ],
+            }),
+        ])
+        .build()
+}
+
+fn button(custom_id: String, label: &str) -> Component {
+    Component::Button(Button {
+        custom_id: Some(custom_id),
+        disabled: false,
+        emoji: None,
+        label: Some(label.to_string()),
+        style: ButtonStyle::Secondary,
+        url: None,
+        sku_id: None,
+    })
+}
+
+/// Turn the choices of a command's first option into a select menu.
+fn select_menu(custom_id: String, placeholder: &str, command: Command, locale: Locale) -> Component {
+    let options = command.options
+        .into_iter()
+        .next()
+        .and_then(|option| option.choices)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|choice| {
+            let CommandOptionChoiceValue::String(value) = choice.value else {
+                return None;
+            };
+            Some(SelectMenuOption {
+                default: false,
+                description: None,
+                emoji: None,
+                label: locale.text(&choice.name).to_string(),
+                value,
+            })
+        })
+        .collect();
+
+    Component::ActionRow(ActionRow {
+        components: vec![
+            Component::SelectMenu(SelectMenu {
+                channel_types: None,
+                custom_id,
+                default_values: None,
+                disabled: false,
+                kind: SelectMenuType::Text,
+                max_values: Some(1),
+                min_values: Some(1),
+                options: Some(options),
+                placeholder: Some(placeholder.to_string()),
+            }),
+        ],
+    })
+}
+
+/// Parse a select menu value back into the option it was built from.
+fn parse_choice<T: CommandOption>(value: String) -> Option<T> {
+    T::from_option(CommandOptionValue::String(value), Default::default(), None).ok()
+}
+
+/// Returns: `(channel, message)`
+fn parse_message_target(target: &str) -> Option<(Id<ChannelMarker>, Id<MessageMarker>)> {
+    let (channel_id, message_id) = target.split_once(':')?;
+    Some((channel_id.parse().ok()?, message_id.parse().ok()?))
+}
+
+/// Returns: `(user, ping)`
+fn parse_user_target(target: &str) -> Option<(Id<UserMarker>, bool)> {
+    let (user_id, ping) = target.split_once(':')?;
+    Some((user_id.parse().ok()?, ping == "1"))
+}
+
+/// A small line linking back to the message being replied to.
+fn reply_line(
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>
+) -> String {
+    let guild = guild_id.map_or_else(|| "@me".to_string(), |id| id.to_string());
+    format!("-# ↪ https://discord.com/channels/{}/{}/{}", guild, channel_id, message_id)
+}
@@ -0,0 +1,347 @@
+//! Offline catalog maintenance: `four catalog dedup` merges nubs that are the
+//! same image under different URLs.
+//!
+//! Every nub is downloaded into the [`MediaCache`] directory so later runs work
+//! offline. Nubs whose first frames have close enough perceptual hashes are
+//! merged into the first of them, keeping all of their tags, and the result is
+//! written back to the catalog file. The merged nub is rated the strictest any
+//! of them was, so merging never makes an NSFW nub show up in safe places.
+//!
+//! Favorites, popularity and tag overrides of the merged-away URLs move to
+//! the nub they were merged into, so run it while the bot is stopped.
+
+use std::{ collections::HashMap, error::Error, sync::Arc };
+
+use image::imageops::FilterType;
+use tokio::task::JoinSet;
+
+use crate::{
+    config::Config,
+    favorites::FavoritesStore,
+    media::MediaCache,
+    nub::{ self, load_nubs, save_nubs, Nub, GLOBAL },
+    rating::ContentRating,
+    store::Store,
+};
+
+/// Most differing hash bits for two nubs to count as the same image.
+const MAX_DISTANCE: u32 = 4;
+
+/// Downloads in flight at once.
+const CONCURRENCY: usize = 16;
+
+pub async fn run(config: &Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let media = Arc::new(MediaCache::from_env()?);
+
+    let path = &config.catalog.path;
+    let nubs = load_nubs(path)?;
+    let hashes = hash_all(&nubs, media).await;
+    let missing = hashes.iter().filter(|hash| hash.is_none()).count();
+
+    // moderators' ratings, as the bot keeps them
+    let ratings = nub::open_ratings(config)?;
+    let rating = |nub: &Nub| {
+        ratings.get(&(GLOBAL, nub.source.to_string())).unwrap_or_else(|| ContentRating::from_tags(&nub.tags))
+    };
+
+    let before = nubs.len();
+    // as they were before merging, for carrying tag overrides over
+    let tags = nubs
+        .iter()
+        .map(|nub| (nub.source.to_string(), nub.tags.clone()))
+        .collect::<HashMap<_, _>>();
+    let (merged, duplicates) = merge(nubs, &hashes, rating);
+    for (nub, rating) in &merged {
+        let key = (GLOBAL, nub.source.to_string());
+        if *rating == ContentRating::from_tags(&nub.tags) {
+            ratings.remove(&key);
+        } else {
+            ratings.update(key, |current| *current = *rating);
+        }
+    }
+    for duplicate in duplicates.keys() {
+        ratings.remove(&(GLOBAL, duplicate.clone()));
+    }
+    ratings.flush()?;
+    move_to_canonical(config, &merged, &duplicates, &tags)?;
+
+    let nubs = merged.into_iter().map(|(nub, _)| nub).collect::<Vec<_>>();
+    save_nubs(path, &nubs)?;
+
+    tracing::info!(before, after = nubs.len(), missing, path = %path.display(), "deduplicated the catalog");
+    Ok(())
+}
+
+/// Hash every nub, in catalog order. Nubs that couldn't be fetched or decoded
+/// have no hash and are left alone.
+async fn hash_all(nubs: &[Nub], media: Arc<MediaCache>) -> Vec<Option<u64>> {
+    let mut hashes = vec![None; nubs.len()];
+    let mut tasks = JoinSet::new();
+
+    for (i, nub) in nubs.iter().enumerate() {
+        if tasks.len() >= CONCURRENCY
+            && let Some(Ok((i, hash))) = tasks.join_next().await
+        {
+            hashes[i] = hash;
+        }
+
+        let media = media.clone();
+        let url = nub.source.to_string();
+        tasks.spawn(async move {
+            let hash = match media.fetch(&url).await {
+                Ok(bytes) => tokio::task::spawn_blocking(move || dhash(&bytes)).await.ok().flatten(),
+                Err(e) => {
+                    tracing::warn!(url, ?e, "couldn't fetch nub");
+                    None
+                }
+            };
+            (i, hash)
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Ok((i, hash)) = result {
+            hashes[i] = hash;
+        }
+    }
+
+    hashes
+}
+
+/// Difference hash of the first frame: shrink to 9x8 grayscale and set a bit
+/// wherever a pixel is brighter than its right neighbour.
+fn dhash(bytes: &[u8]) -> Option<u64> {
+    let image = image::load_from_memory(bytes).ok()?;
+    let small = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Some(hash)
+}
+
+/// Fold each nub into the first earlier one that looks the same, rating it
+/// the strictest `rating` gives any of them.
+///
+/// Returns: the merged nubs, and the URL each merged-away one went into, by
+/// its own URL
+fn merge(
+    nubs: Vec<Nub>,
+    hashes: &[Option<u64>],
+    rating: impl Fn(&Nub) -> ContentRating
+) -> (Vec<(Nub, ContentRating)>, HashMap<String, String>) {
+    let mut merged: Vec<(Nub, ContentRating, Option<u64>)> = Vec::with_capacity(nubs.len());
+    let mut duplicates = HashMap::new();
+
+    for (nub, hash) in nubs.into_iter().zip(hashes.iter().copied()) {
+        let nub_rating = rating(&nub);
+        let canonical = hash.and_then(|hash| {
+            merged.iter_mut().find(|(_, _, other)| {
+                other.is_some_and(|other| (hash ^ other).count_ones() <= MAX_DISTANCE)
+            })
+        });
+
+        match canonical {
+            Some((canonical, canonical_rating, _)) => {
+                tracing::debug!(duplicate = %nub.source, canonical = %canonical.source, "merging");
+                duplicates.insert(nub.source.to_string(), canonical.source.to_string());
+                *canonical_rating = (*canonical_rating).max(nub_rating);
+                for tag in nub.tags {
+                    if !canonical.tags.contains(&tag) {
+                        canonical.tags.push(tag);
+                    }
+                }
+            }
+            None => merged.push((nub, nub_rating, hash)),
+        }
+    }
+
+    (merged.into_iter().map(|(nub, rating, _)| (nub, rating)).collect(), duplicates)
+}
+
+/// Move what the bot keeps by URL from each merged-away nub to the one it
+/// went into: favorites, popularity, and tag overrides. The merged nub keeps
+/// every tag any of them had once its override was applied.
+fn move_to_canonical(
+    config: &Config,
+    merged: &[(Nub, ContentRating)],
+    duplicates: &HashMap<String, String>,
+    tags: &HashMap<String, Vec<Box<str>>>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let favorites = FavoritesStore::open(config.storage.path("favorites.bin"))?;
+    for (user, urls) in favorites.entries() {
+        if !urls.iter().any(|url| duplicates.contains_key(url)) {
+            continue;
+        }
+        let mut moved = Vec::with_capacity(urls.len());
+        for url in urls {
+            let url = duplicates.get(&url).cloned().unwrap_or(url);
+            if !moved.contains(&url) {
+                moved.push(url);
+            }
+        }
+        favorites.update(user, |current| *current = moved);
+    }
+    favorites.flush()?;
+
+    let popularity = Store::<String, u64>::open(config.storage.path("popularity.bin"))?;
+    for (duplicate, canonical) in duplicates {
+        if let Some(count) = popularity.remove(duplicate) {
+            popularity.update(canonical.clone(), |total| *total += count);
+        }
+    }
+    popularity.flush()?;
+
+    let overrides = nub::open_tag_overrides(config)?;
+    let mut groups = HashMap::<&str, Vec<&str>>::new();
+    for (duplicate, canonical) in duplicates {
+        groups.entry(canonical).or_default().push(duplicate);
+    }
+    for (nub, _) in merged {
+        let Some(group) = groups.get(&*nub.source) else {
+            continue;
+        };
+        let urls = std::iter::once(&*nub.source).chain(group.iter().copied()).collect::<Vec<_>>();
+        if !urls.iter().any(|url| overrides.get(&(GLOBAL, url.to_string())).is_some()) {
+            continue;
+        }
+
+        let mut kept = Vec::new();
+        for url in urls {
+            let original = tags.get(url).map_or(&[][..], Vec::as_slice);
+            let edited = match overrides.remove(&(GLOBAL, url.to_string())) {
+                Some(tag_override) => tag_override.apply(original),
+                None => original.iter().map(|tag| tag.to_string()).collect(),
+            };
+            for tag in edited {
+                if !kept.contains(&tag) {
+                    kept.push(tag);
+                }
+            }
+        }
+        let catalog = nub.tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+        overrides.update((GLOBAL, nub.source.to_string()), |tag_override| {
+            tag_override.added = kept.iter().filter(|tag| !catalog.contains(tag)).cloned().collect();
+            tag_override.removed = catalog.iter().filter(|tag| !kept.contains(tag)).cloned().collect();
+        });
+    }
+    overrides.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ GrayImage, ImageFormat, Luma };
+
+    use super::*;
+    use crate::tests::TempDir;
+
+    /// A left-to-right gradient, with `bumps` bright spots along the top.
+    fn gradient(bumps: u32) -> Vec<u8> {
+        let mut image = GrayImage::from_fn(90, 80, |x, _| Luma([(x * 2) as u8]));
+        for bump in 0..bumps {
+            for y in 0..10 {
+                for x in 0..10 {
+                    image.put_pixel(bump * 20 + x, y, Luma([255]));
+                }
+            }
+        }
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).unwrap();
+        bytes
+    }
+
+    fn nub(url: &str, tags: &[&str]) -> Nub {
+        Nub { source: url.into(), tags: tags.iter().map(|&tag| tag.into()).collect() }
+    }
+
+    fn urls(merged: &[(Nub, ContentRating)]) -> Vec<&str> {
+        merged.iter().map(|(nub, _)| &*nub.source).collect()
+    }
+
+    #[test]
+    fn same_images_hash_close() {
+        let hash = dhash(&gradient(0)).unwrap();
+        assert_eq!(dhash(&gradient(0)), Some(hash));
+
+        let distance = |bumps| (dhash(&gradient(bumps)).unwrap() ^ hash).count_ones();
+        assert!(distance(1) <= MAX_DISTANCE, "{}", distance(1));
+        assert!(distance(4) > MAX_DISTANCE, "{}", distance(4));
+
+        assert_eq!(dhash(b"not an image"), None);
+    }
+
+    #[test]
+    fn merges_up_to_the_distance() {
+        let nubs = vec![
+            nub("https://example.com/a.gif", &["cat"]),
+            nub("https://example.com/b.gif", &["cat"]),
+            nub("https://example.com/c.gif", &["cat"]),
+            nub("https://example.com/d.gif", &["cat"]),
+        ];
+        let just_over = (1 << (MAX_DISTANCE + 1)) - 1;
+        let hashes = [Some(0), Some((1 << MAX_DISTANCE) - 1), Some(just_over), None];
+
+        let (merged, duplicates) = merge(nubs, &hashes, |_| ContentRating::Safe);
+        assert_eq!(urls(&merged), ["https://example.com/a.gif", "https://example.com/c.gif", "https://example.com/d.gif"]);
+        assert_eq!(duplicates, HashMap::from([("https://example.com/b.gif".to_string(), "https://example.com/a.gif".to_string())]));
+    }
+
+    #[test]
+    fn merged_nubs_keep_every_tag_and_the_strictest_rating() {
+        let nubs = vec![
+            nub("https://example.com/a.gif", &["cat", "sleepy"]),
+            nub("https://example.com/b.gif", &["sleepy", "bed"]),
+            nub("https://example.com/c.gif", &["cat"]),
+        ];
+        let rated = |nub: &Nub| match &*nub.source {
+            "https://example.com/c.gif" => ContentRating::Nsfw,
+            _ => ContentRating::Safe,
+        };
+
+        let (merged, _) = merge(nubs, &[Some(0), Some(1), Some(3)], rated);
+        assert_eq!(urls(&merged), ["https://example.com/a.gif"]);
+        assert_eq!(&*merged[0].0.tags, ["cat".into(), "sleepy".into(), "bed".into()] as [Box<str>; 3]);
+        assert_eq!(merged[0].1, ContentRating::Nsfw);
+    }
+
+    #[test]
+    fn merged_away_urls_hand_over_favorites_popularity_and_tag_overrides() {
+        let dir = TempDir::new();
+        let config = dir.config();
+        let (a, b, c) = ("https://example.com/a.gif", "https://example.com/b.gif", "https://example.com/c.gif");
+        let nubs = vec![nub(a, &["cat", "sleepy"]), nub(b, &["sleepy", "bed"]), nub(c, &["dog"])];
+        let tags = nubs.iter().map(|nub| (nub.source.to_string(), nub.tags.clone())).collect();
+
+        let favorites = FavoritesStore::open(config.storage.path("favorites.bin")).unwrap();
+        favorites.update(1, |urls| urls.extend([b, a, c].map(String::from)));
+        favorites.flush().unwrap();
+        let popularity = Store::<String, u64>::open(config.storage.path("popularity.bin")).unwrap();
+        popularity.update(a.to_string(), |count| *count = 2);
+        popularity.update(b.to_string(), |count| *count = 3);
+        popularity.flush().unwrap();
+        let overrides = nub::open_tag_overrides(&config).unwrap();
+        overrides.update((GLOBAL, b.to_string()), |tag_override| tag_override.removed.push("bed".to_string()));
+        overrides.flush().unwrap();
+
+        let (merged, duplicates) = merge(nubs, &[Some(0), Some(1), None], |_| ContentRating::Safe);
+        move_to_canonical(&config, &merged, &duplicates, &tags).unwrap();
+
+        let favorites = FavoritesStore::open(config.storage.path("favorites.bin")).unwrap();
+        assert_eq!(favorites.get(&1).unwrap(), [a, c]);
+        let popularity = Store::<String, u64>::open(config.storage.path("popularity.bin")).unwrap();
+        assert_eq!(popularity.get(&a.to_string()), Some(5));
+        assert_eq!(popularity.get(&b.to_string()), None);
+        let overrides = nub::open_tag_overrides(&config).unwrap();
+        assert!(overrides.get(&(GLOBAL, b.to_string())).is_none());
+        let tag_override = overrides.get(&(GLOBAL, a.to_string())).unwrap();
+        assert!(tag_override.added.is_empty());
+        assert_eq!(tag_override.removed, ["bed"]);
+    }
+}
@@ -0,0 +1,160 @@
+//! Nubs users saved with the favorite button on `/nub` replies.
+
+use std::{ error::Error, sync::Arc };
+
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::{
+    application::{
+        command::{ CommandOptionChoice, CommandOptionChoiceValue },
+        interaction::Interaction,
+    },
+    channel::message::MessageFlags,
+    http::interaction::{ InteractionResponse, InteractionResponseType },
+    id::{ marker::UserMarker, Id },
+};
+use twilight_util::builder::{ embed::EmbedBuilder, InteractionResponseDataBuilder };
+
+use crate::{ locale::Locale, logging::Timed, rating::ContentRating, store::Store, AppState };
+
+/// User ID → favorite nub URLs, oldest first.
+pub type FavoritesStore = Store<u64, Vec<String>>;
+
+pub const FAVORITE_ID: &str = "nub-favorite";
+
+/// As many as fit in one autocomplete response.
+const MAX_FAVORITES: usize = 25;
+
+#[derive(CreateCommand, CommandModel)]
+#[command(
+    name = "nub-favorites",
+    desc = "see the nubs you've saved",
+    contexts = "guild bot_dm private_channel"
+)]
+pub struct NubFavoritesCommand;
+
+/// Autocomplete choices for a user's favorites, used when they haven't typed
+/// anything yet, as seen from `guild`. Favorites rated above `max_rating` are
+/// left out.
+pub fn choices(
+    state: &AppState,
+    user_id: Id<UserMarker>,
+    guild: Option<u64>,
+    max_rating: ContentRating
+) -> Result<Vec<CommandOptionChoice>, Box<dyn Error + Send + Sync>> {
+    let mut choices = Vec::new();
+    for url in state.favorites.get(&user_id.get()).unwrap_or_default() {
+        if state.nub_finder.rating(&url, guild)?.is_some_and(|rating| rating > max_rating) {
+            continue;
+        }
+        let name = match state.nub_finder.get(&url, guild)? {
+            Some((_, keywords)) => format!("⭐ {}", keywords),
+            None => format!("⭐ {}", url),
+        };
+        choices.push(CommandOptionChoice {
+            name: crate::choice_name(name),
+            name_localizations: None,
+            value: CommandOptionChoiceValue::String(format!("nub:{}", url)),
+        });
+    }
+    Ok(choices)
+}
+
+pub async fn handle_command(
+    interaction: &Interaction,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let favorites = interaction
+        .author_id()
+        .and_then(|id| state.favorites.get(&id.get()))
+        .unwrap_or_default();
+    let locale = Locale::of(interaction);
+
+    let data = if favorites.is_empty() {
+        InteractionResponseDataBuilder::new()
+            .content(locale.text("no favorites yet, hit ⭐ on a /nub to save one"))
+            .flags(MessageFlags::EPHEMERAL)
+            .build()
+    } else {
+        let finder = state.clone();
+        let guild = interaction.guild_id.map(|id| id.get());
+        let lines = tokio::task::spawn_blocking(move || {
+            favorites
+                .into_iter()
+                .enumerate()
+                .map(|(i, url)| {
+                    let keywords = finder.nub_finder
+                        .get(&url, guild)?
+                        .map_or_else(|| "nub".to_string(), |(_, keywords)| keywords);
+                    Ok(format!("{}. [{}]({})", i + 1, keywords, url))
+                })
+                .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()
+        }).await??;
+
+        InteractionResponseDataBuilder::new()
+            .embeds([EmbedBuilder::new().title(locale.text("your favorite nubs")).description(lines.join("\n")).build()])
+            .flags(MessageFlags::EPHEMERAL)
+            .build()
+    };
+
+    http.interaction(interaction.application_id).create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(data),
+        })
+    ).timed().await?;
+
+    Ok(())
+}
+
+/// Toggle the nub shown in the message as a favorite of whoever clicked.
+pub async fn handle_component(
+    interaction: &Interaction,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let url = interaction.message
+        .as_ref()
+        .and_then(|message| message.embeds.first())
+        .and_then(crate::media::shown_url);
+
+    let content = match (interaction.author_id(), url) {
+        (Some(user_id), Some(url)) => {
+            let content = state.favorites.update(user_id.get(), |favorites| {
+                if let Some(i) = favorites.iter().position(|favorite| *favorite == url) {
+                    favorites.remove(i);
+                    "removed from your favorites"
+                } else if favorites.len() >= MAX_FAVORITES {
+                    "you already have 25 favorites, remove one first"
+                } else {
+                    favorites.push(url);
+                    "saved to your favorites ⭐"
+                }
+            });
+            let store = state.clone();
+            tokio::task::spawn_blocking(move || store.favorites.flush()).await??;
+            content
+        }
+        _ => "there's no nub to save here",
+    };
+    let content = Locale::of(interaction).text(content);
+
+    http.interaction(interaction.application_id).create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(
+                InteractionResponseDataBuilder::new()
+                    .content(content)
+                    .flags(MessageFlags::EPHEMERAL)
+                    .build()
+            ),
+        })
+    ).timed().await?;
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use twilight_cache_inmemory::ResourceType;
+use twilight_gateway::Intents;
+
+/// Optional features, toggled through the environment.
+///
+/// Each one decides which gateway intents we ask for and what the in-memory
+/// cache keeps, so nothing is received or stored that no feature reads.
+pub struct Features {
+    /// Cache messages so the bot can look up its own replies (`CACHE_MESSAGES=1`).
+    pub cache_messages: bool,
+    /// Answer plain messages in opted-in guilds (`AUTO_RESPOND=1`, or
+    /// `AUTO_RESPOND_GUILDS=<id>,...` to opt guilds in by default).
+    pub auto_respond: bool,
+    /// Keep local copies of nubs to send when their hosts are down (`MEDIA_CACHE=1`).
+    pub media_cache: bool,
+    /// Write interactions and responses to a file to replay (`RECORD_INTERACTIONS=<file>`).
+    pub record_interactions: bool,
+}
+
+impl Features {
+    pub fn from_env() -> Self {
+        Self {
+            cache_messages: flag("CACHE_MESSAGES"),
+            auto_respond: flag("AUTO_RESPOND") || !list("AUTO_RESPOND_GUILDS").is_empty(),
+            media_cache: flag("MEDIA_CACHE"),
+            record_interactions: dotenvy::var("RECORD_INTERACTIONS").is_ok_and(|path| !path.is_empty()),
+        }
+    }
+
+    pub fn intents(&self) -> Intents {
+        // guild and channel names for logs
+        let mut intents = Intents::GUILDS;
+        if self.cache_messages {
+            intents |= Intents::GUILD_MESSAGES;
+        }
+        if self.auto_respond {
+            intents |= Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT;
+        }
+        intents
+    }
+
+    pub fn resource_types(&self) -> ResourceType {
+        let mut resource_types = ResourceType::GUILD | ResourceType::CHANNEL;
+        if self.cache_messages {
+            resource_types |= ResourceType::MESSAGE;
+        }
+        resource_types
+    }
+}
+
+fn flag(name: &str) -> bool {
+    dotenvy::var(name).is_ok_and(|value| matches!(value.as_str(), "1" | "true"))
+}
+
+/// A comma-separated list, empty when unset.
+pub fn list(name: &str) -> Vec<String> {
+    dotenvy::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,146 @@
+//! Whether the bot is working, served at `/healthz` and `/readyz` by
+//! [`crate::server`].
+//!
+//! The gateway loop reports shard state and heartbeats here. The bot is
+//! healthy while the shard isn't fatally closed and heartbeats keep being
+//! acknowledged, and ready when it's also connected and has nubs to search.
+//! The catalog's age is reported but never makes it degraded, since it's
+//! only downloaded when missing.
+
+use std::{ sync::{ LazyLock, Mutex }, time::{ Duration, Instant } };
+
+use twilight_gateway::{ Event, ShardState };
+
+pub static HEALTH: LazyLock<Health> = LazyLock::new(Health::new);
+
+/// How long without a heartbeat ack, or a first one after starting, before
+/// the connection counts as hung. Discord asks for one about every 41s.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(180);
+
+pub struct Health {
+    started: Instant,
+    shard: Mutex<Shard>,
+}
+
+#[derive(Default)]
+struct Shard {
+    /// `None` until the first event.
+    state: Option<ShardState>,
+    last_ready: Option<Instant>,
+    last_heartbeat_ack: Option<Instant>,
+}
+
+/// What `/healthz` and `/readyz` answer with.
+#[derive(Debug, serde::Serialize)]
+pub struct Report {
+    pub healthy: bool,
+    pub ready: bool,
+    pub shard: &'static str,
+    pub last_ready_seconds: Option<f64>,
+    pub last_heartbeat_ack_seconds: Option<f64>,
+    pub index_documents: u64,
+    pub catalog_age_seconds: Option<f64>,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self { started: Instant::now(), shard: Mutex::default() }
+    }
+
+    /// Note an event from the shard, and the state it left the shard in.
+    pub fn observe(&self, event: &Event, state: ShardState) {
+        let mut shard = self.shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        shard.state = Some(state);
+        match event {
+            Event::Ready(_) => shard.last_ready = Some(Instant::now()),
+            Event::GatewayHeartbeatAck => shard.last_heartbeat_ack = Some(Instant::now()),
+            _ => (),
+        }
+    }
+
+    pub fn report(&self, index_documents: u64, catalog_age: Option<Duration>) -> Report {
+        let shard = self.shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let since_heartbeat = shard.last_heartbeat_ack.unwrap_or(self.started).elapsed();
+
+        let healthy = shard.state != Some(ShardState::FatallyClosed) && since_heartbeat < HEARTBEAT_TIMEOUT;
+        let ready = healthy
+            && shard.state == Some(ShardState::Active)
+            && shard.last_ready.is_some()
+            && index_documents > 0;
+
+        Report {
+            healthy,
+            ready,
+            shard: match shard.state {
+                None => "starting",
+                Some(ShardState::Active) => "active",
+                Some(ShardState::Disconnected { .. }) => "disconnected",
+                Some(ShardState::FatallyClosed) => "fatally_closed",
+                Some(ShardState::Identifying) => "identifying",
+                Some(ShardState::Resuming) => "resuming",
+            },
+            last_ready_seconds: shard.last_ready.map(|at| at.elapsed().as_secs_f64()),
+            last_heartbeat_ack_seconds: shard.last_heartbeat_ack.map(|at| at.elapsed().as_secs_f64()),
+            index_documents,
+            catalog_age_seconds: catalog_age.map(|age| age.as_secs_f64()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::gateway::payload::incoming::Ready;
+
+    use super::*;
+
+    fn ready() -> Event {
+        Event::Ready(Box::new(serde_json::from_value::<Ready>(serde_json::json!({
+            "v": 10,
+            "user": { "id": "100", "username": "four", "discriminator": "0", "avatar": null, "bot": true, "mfa_enabled": false },
+            "guilds": [],
+            "session_id": "session",
+            "resume_gateway_url": "wss://gateway.discord.gg",
+            "application": { "id": "100", "flags": 0 },
+        })).unwrap()))
+    }
+
+    #[test]
+    fn ready_once_connected_with_nubs() {
+        let health = Health::new();
+        let report = health.report(3, None);
+        assert!(report.healthy && !report.ready);
+        assert_eq!(report.shard, "starting");
+
+        health.observe(&ready(), ShardState::Active);
+        health.observe(&Event::GatewayHeartbeatAck, ShardState::Active);
+        let report = health.report(3, Some(Duration::from_secs(60)));
+        assert!(report.healthy && report.ready, "{:?}", report);
+        assert!(report.last_ready_seconds.is_some() && report.last_heartbeat_ack_seconds.is_some());
+        assert_eq!(report.catalog_age_seconds, Some(60.0));
+
+        assert!(!health.report(0, None).ready);
+    }
+
+    #[test]
+    fn degraded_when_disconnected_or_closed() {
+        let health = Health::new();
+        health.observe(&ready(), ShardState::Active);
+        health.observe(&Event::GatewayReconnect, ShardState::Disconnected { reconnect_attempts: 0 });
+        let report = health.report(3, None);
+        assert!(report.healthy && !report.ready);
+        assert_eq!(report.shard, "disconnected");
+
+        health.observe(&Event::GatewayReconnect, ShardState::FatallyClosed);
+        let report = health.report(3, None);
+        assert!(!report.healthy && !report.ready);
+    }
+
+    #[test]
+    fn unhealthy_without_heartbeats() {
+        let health = Health { started: Instant::now() - HEARTBEAT_TIMEOUT, shard: Mutex::default() };
+        assert!(!health.report(3, None).healthy);
+
+        health.observe(&Event::GatewayHeartbeatAck, ShardState::Active);
+        assert!(health.report(3, None).healthy);
+    }
+}
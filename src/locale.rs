@@ -0,0 +1,173 @@
+//! Translations of command descriptions and replies.
+//!
+//! Each file in `locales/` maps the English text, as written in the code, to
+//! its translation. Text without one stays English, so the code only ever
+//! names the English version. Commands get their localizations when they're
+//! registered, and replies follow the locale of whoever triggered them.
+
+use std::{ collections::HashMap, fmt::Display, sync::LazyLock };
+
+use twilight_model::application::{
+    command::{ Command, CommandOption, CommandType },
+    interaction::Interaction,
+};
+
+/// Bundled translations, by Discord locale.
+const LOCALES: &[(&str, &str)] = &[
+    ("es-ES", include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/locales/es-ES.json"))),
+];
+
+type Table = HashMap<String, String>;
+
+static TABLES: LazyLock<Vec<(&str, Table)>> = LazyLock::new(|| {
+    LOCALES
+        .iter()
+        .map(|(code, json)| {
+            let table = serde_json::from_str(json).unwrap_or_else(|e| panic!("locales/{}.json is invalid: {}", code, e));
+            (*code, table)
+        })
+        .collect()
+});
+
+/// The translations for one locale, or none for English.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Locale {
+    table: Option<&'static Table>,
+}
+
+impl Locale {
+    /// The closest locale we have to `code`: the same one, or another for the
+    /// same language, like `es-ES` for `es-419`.
+    pub fn new(code: &str) -> Self {
+        let language = code.split('-').next().unwrap_or(code);
+        let table = TABLES
+            .iter()
+            .find(|(candidate, _)| *candidate == code)
+            .or_else(|| TABLES.iter().find(|(candidate, _)| candidate.split('-').next() == Some(language)))
+            .map(|(_, table)| table);
+
+        Self { table }
+    }
+
+    /// The locale of the user behind an interaction.
+    pub fn of(interaction: &Interaction) -> Self {
+        interaction.locale.as_deref().map(Self::new).unwrap_or_default()
+    }
+
+    /// The locale of the server an interaction came from, for messages
+    /// everyone there sees. Falls back to the user's outside of servers.
+    pub fn of_guild(interaction: &Interaction) -> Self {
+        interaction.guild_locale
+            .as_deref()
+            .or(interaction.locale.as_deref())
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    /// `english`, translated if there's a translation.
+    pub fn text(self, english: &str) -> &str {
+        self.table
+            .and_then(|table| table.get(english))
+            .map_or(english, String::as_str)
+    }
+
+    /// `english` translated, with each `{}` replaced by the next of `args`.
+    ///
+    /// `Sync` so the arguments can live across an `.await`.
+    pub fn format(self, english: &str, args: &[&(dyn Display + Sync)]) -> String {
+        let mut args = args.iter();
+        let mut parts = self.text(english).split("{}");
+        let mut formatted = parts.next().unwrap_or_default().to_string();
+        for part in parts {
+            if let Some(arg) = args.next() {
+                formatted.push_str(&arg.to_string());
+            }
+            formatted.push_str(part);
+        }
+        formatted
+    }
+}
+
+/// Add every translation we have of a command's description, options and
+/// choices. Context menu commands get their name translated too, since it's
+/// what users read; slash command names stay as they are.
+pub fn localize(command: &mut Command) {
+    if command.kind != CommandType::ChatInput {
+        command.name_localizations = translations(&command.name);
+    }
+    if !command.description.is_empty() {
+        command.description_localizations = translations(&command.description);
+    }
+    for option in &mut command.options {
+        localize_option(option);
+    }
+}
+
+fn localize_option(option: &mut CommandOption) {
+    option.description_localizations = translations(&option.description);
+    for choice in option.choices.iter_mut().flatten() {
+        choice.name_localizations = translations(&choice.name);
+    }
+    for option in option.options.iter_mut().flatten() {
+        localize_option(option);
+    }
+}
+
+/// `english` in every locale that translates it.
+fn translations(english: &str) -> Option<HashMap<String, String>> {
+    let translations = TABLES
+        .iter()
+        .filter_map(|(code, table)| Some((code.to_string(), table.get(english)?.clone())))
+        .collect::<HashMap<_, _>>();
+
+    (!translations.is_empty()).then_some(translations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_locales_parse() {
+        assert_eq!(TABLES.len(), LOCALES.len());
+    }
+
+    #[test]
+    fn replies_follow_the_locale() {
+        let english = "i couldn't find that nub :(";
+        assert_eq!(Locale::default().text(english), english);
+        assert_eq!(Locale::new("en-US").text(english), english);
+        assert_ne!(Locale::new("es-ES").text(english), english);
+        // other Spanish locales use the closest one we have
+        assert_eq!(Locale::new("es-419").text(english), Locale::new("es-ES").text(english));
+    }
+
+    #[test]
+    fn format_fills_placeholders_in_order() {
+        assert_eq!(Locale::default().format("`{}` is now disabled", &[&"nub"]), "`nub` is now disabled");
+        assert_eq!(Locale::new("es-ES").format("`{}` is now disabled", &[&"nub"]), "`nub` ya no se puede usar");
+    }
+
+    #[test]
+    fn every_command_is_translated() {
+        let variants = crate::variants::Variants::load().unwrap();
+        for command in crate::commands(&variants) {
+            if command.kind == CommandType::ChatInput {
+                assert!(command.description_localizations.is_some(), "/{} has no translations", command.name);
+            } else {
+                assert!(command.name_localizations.is_some(), "{} has no translations", command.name);
+            }
+
+            let mut options = command.options.iter().collect::<Vec<_>>();
+            while let Some(option) = options.pop() {
+                assert!(
+                    option.description_localizations.is_some(),
+                    "`{}` on /{} has no translations",
+                    option.name,
+                    command.name
+                );
+                options.extend(option.options.iter().flatten());
+            }
+        }
+    }
+}
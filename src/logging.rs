@@ -0,0 +1,179 @@
+//! Log output, and the context logged with it.
+//!
+//! Levels come from `RUST_LOG`, like
+//! `info,four::nub=debug,twilight_gateway=warn`, and are `info` when it's
+//! unset or invalid. `LOG_FORMAT=json` writes a JSON object per line instead
+//! of text, for log collectors.
+//!
+//! Every event is handled inside a span naming the shard and, for
+//! interactions, who used which command where, so anything logged while
+//! handling it says which interaction it was about. Interaction responses
+//! are awaited with [`Timed::timed`], which logs how long Discord took at
+//! `debug`.
+
+use std::{ any::type_name, future::IntoFuture, time::Instant };
+
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+use twilight_gateway::{ Event, ShardId };
+use twilight_http::Response;
+
+use crate::metrics;
+
+pub fn init() {
+    let filter = dotenvy::var("RUST_LOG")
+        .ok()
+        .filter(|directives| !directives.trim().is_empty())
+        .and_then(|directives| EnvFilter::try_new(directives).ok())
+        .unwrap_or_else(|| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match dotenvy::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().with_current_span(true).with_span_list(false).init(),
+        _ => builder.init(),
+    }
+}
+
+/// The span to handle an event from `shard` in.
+pub fn event_span(event: &Event, shard: ShardId) -> Span {
+    let Event::InteractionCreate(interaction) = event else {
+        return tracing::info_span!("event", shard = shard.number(), kind = ?event.kind());
+    };
+
+    let interaction = &interaction.0;
+    let (kind, command) = metrics::describe(interaction);
+    tracing::info_span!(
+        "interaction",
+        shard = shard.number(),
+        id = interaction.id.get(),
+        kind,
+        command,
+        guild = interaction.guild_id.map(|id| id.get()),
+        channel = interaction.channel.as_ref().map(|channel| channel.id.get()),
+        user = interaction.author_id().map(|id| id.get()),
+    )
+}
+
+/// Awaiting a Discord request while timing it.
+pub trait Timed<T>: IntoFuture<Output = Result<Response<T>, twilight_http::Error>> + Sized {
+    /// Await the request, logging how long it took and how it went, named
+    /// after its type, like `CreateResponse`.
+    fn timed(self) -> impl Future<Output = Self::Output> + Send
+        where Self: Send, Self::IntoFuture: Send
+    {
+        let request = type_name::<Self>()
+            .split('<')
+            .next()
+            .and_then(|path| path.rsplit("::").next())
+            .unwrap_or_default();
+        async move {
+            let started = Instant::now();
+            let result = self.await;
+            let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+            match &result {
+                Ok(response) => tracing::debug!(request, elapsed_ms, status = response.status().get(), "discord request"),
+                Err(e) => tracing::debug!(request, elapsed_ms, error = %e, "discord request failed"),
+            }
+            result
+        }
+    }
+}
+
+impl<T, F> Timed<T> for F where F: IntoFuture<Output = Result<Response<T>, twilight_http::Error>> {}
+
+#[cfg(test)]
+mod tests {
+    use std::{ io::Write, sync::{ Arc, Mutex } };
+
+    use serde_json::{ json, Value };
+    use twilight_model::{
+        application::interaction::Interaction,
+        gateway::payload::incoming::InteractionCreate,
+        http::interaction::{ InteractionResponse, InteractionResponseType },
+    };
+
+    use super::*;
+    use crate::{
+        mock::MockDiscord,
+        tests::{ command, string, CHANNEL_ID, GUILD_ID, INTERACTION_ID, USER_ID },
+    };
+
+    /// Log lines written while the subscriber is in use.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        /// A subscriber writing JSON here, like `LOG_FORMAT=json` does.
+        fn subscriber(&self) -> impl tracing::Subscriber + Send + Sync {
+            let capture = self.clone();
+            tracing_subscriber::fmt()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_max_level(tracing::Level::DEBUG)
+                .with_writer(move || capture.clone())
+                .finish()
+        }
+
+        fn lines(&self) -> Vec<Value> {
+            let written = self.0.lock().unwrap();
+            serde_json::Deserializer::from_slice(&written).into_iter().map(Result::unwrap).collect()
+        }
+    }
+
+    #[test]
+    fn logs_inside_the_interaction_span() {
+        let capture = Capture::default();
+        let subscriber = capture.subscriber();
+
+        let interaction = serde_json::from_value(command("nub", json!([string("query", "sleepy")]))).unwrap();
+        let event = Event::InteractionCreate(Box::new(InteractionCreate(interaction)));
+        tracing::subscriber::with_default(subscriber, || {
+            let _entered = event_span(&event, ShardId::ONE).entered();
+            tracing::info!("handling");
+        });
+
+        let line = &capture.lines()[0];
+        assert_eq!(line["fields"]["message"], "handling");
+        let span = &line["span"];
+        assert_eq!(span["name"], "interaction");
+        assert_eq!(span["shard"], 0);
+        assert_eq!(span["id"].to_string(), INTERACTION_ID);
+        assert_eq!(span["kind"], "command");
+        assert_eq!(span["command"], "nub");
+        assert_eq!(span["guild"].to_string(), GUILD_ID);
+        assert_eq!(span["channel"].to_string(), CHANNEL_ID);
+        assert_eq!(span["user"].to_string(), USER_ID);
+    }
+
+    #[tokio::test]
+    async fn times_responses() {
+        let capture = Capture::default();
+        let _default = tracing::subscriber::set_default(capture.subscriber());
+        let discord = MockDiscord::start().await.unwrap();
+
+        let interaction = serde_json::from_value::<Interaction>(command("hello", json!([]))).unwrap();
+        discord.client().interaction(interaction.application_id).create_response(
+            interaction.id,
+            &interaction.token,
+            &(InteractionResponse { kind: InteractionResponseType::DeferredChannelMessageWithSource, data: None })
+        ).timed().await.unwrap();
+
+        let lines = capture.lines();
+        let line = lines.iter().find(|line| line["fields"]["message"] == "discord request").unwrap();
+        assert_eq!(line["level"], "DEBUG");
+        assert_eq!(line["fields"]["request"], "CreateResponse");
+        assert_eq!(line["fields"]["status"], 200);
+        assert!(line["fields"]["elapsed_ms"].is_f64());
+    }
+}
@@ -1,78 +1,280 @@
 mod nub;
 mod calc;
-
-use std::{ error::Error, sync::Arc };
-
-use twilight_cache_inmemory::{ DefaultInMemoryCache, ResourceType };
-use twilight_gateway::{ Event, EventTypeFlags, Intents, Shard, ShardId, StreamExt as _ };
+mod caption;
+mod cli;
+mod auto_respond;
+mod buttons;
+mod config;
+mod context_menu;
+mod dedup;
+mod favorites;
+mod features;
+mod health;
+mod locale;
+mod logging;
+mod media;
+mod metrics;
+mod mock;
+mod overlay;
+mod rating;
+mod recorder;
+mod response;
+mod server;
+mod settings;
+mod stats;
+mod store;
+mod submissions;
+mod tags;
+mod variants;
+
+#[cfg(test)]
+mod tests;
+
+use std::{ error::Error, sync::Arc, time::{ Duration, Instant } };
+
+use clap::Parser as _;
+use tracing::Instrument as _;
+use twilight_cache_inmemory::DefaultInMemoryCache;
+use twilight_gateway::{ Event, EventTypeFlags, Shard, ShardId, StreamExt as _ };
 use twilight_http::Client as HttpClient;
 use twilight_interactions::command::{ CommandModel, CommandOption, CreateCommand, CreateOption };
 use twilight_model::{
     application::{
-        command::{ CommandOptionChoice, CommandOptionChoiceValue },
-        interaction::{ application_command::CommandOptionValue, InteractionData },
+        command::{ Command, CommandOptionChoice, CommandOptionChoiceValue, CommandType },
+        interaction::{
+            application_command::CommandOptionValue,
+            Interaction,
+            InteractionData,
+            InteractionType,
+        },
     },
-    http::{ attachment::Attachment, interaction::{ InteractionResponse, InteractionResponseType } },
+    channel::message::MessageFlags,
+    http::interaction::{ InteractionResponse, InteractionResponseType },
+    id::{ marker::GuildMarker, Id },
 };
-use twilight_util::builder::{
-    embed::{ EmbedBuilder, ImageSource },
-    InteractionResponseDataBuilder,
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::{
+    auto_respond::AutoResponder,
+    caption::NubCaptionCommand,
+    cli::{ Cli, CliCommand },
+    config::Config,
+    favorites::{ FavoritesStore, NubFavoritesCommand },
+    features::Features,
+    health::HEALTH,
+    locale::Locale,
+    logging::Timed,
+    media::MediaCache,
+    metrics::METRICS,
+    nub::{ get_nubs, NubFinder },
+    overlay::FourOnCommand,
+    rating::ContentRating,
+    recorder::Recorder,
+    settings::{ FourConfigCommand, SettingsStore },
+    stats::{ FourStatsCommand, Stats },
+    submissions::{ NubAddCommand, SubmissionStore },
+    tags::NubTagsExportCommand,
+    variants::Variants,
 };
 
-use crate::nub::{ get_nubs, NubFinder };
-
-const FOUR_MP3: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/four.mp3"));
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    tracing_subscriber::fmt::init();
     dotenvy::dotenv_override().ok();
+    logging::init();
 
-    let token = dotenvy::var("DISCORD_TOKEN")?;
+    let cli = Cli::parse();
+    let config = Config::load()?;
+
+    match cli.command.unwrap_or(CliCommand::Run) {
+        CliCommand::Run => run(config).await,
+        CliCommand::RegisterCommands { guild, global, dry_run } => cli::register_commands(guild, global, dry_run).await,
+        CliCommand::Catalog(command) => cli::catalog(command, &config).await,
+        CliCommand::Search { query, guild, rating } => cli::search(&query, guild, rating, &config).await,
+        CliCommand::Replay { path, data_dir } => cli::replay(&path, data_dir, config).await,
+    }
+}
 
-    let intents = Intents::empty();
-    let mut shard = Shard::new(ShardId::ONE, token.clone(), intents);
+/// Connect to the gateway and handle events until it closes.
+async fn run(config: Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // without an owner, the nubs every server shares could never be edited
+    if config.catalog.owner.is_none() {
+        return Err("catalog.owner (CATALOG_OWNER) must be set to run the bot".into());
+    }
+    let token = dotenvy::var("DISCORD_TOKEN")?;
 
-    let http = Arc::new(HttpClient::new(token));
+    let features = Features::from_env();
+    let mut shard = Shard::new(ShardId::ONE, token.clone(), features.intents());
+
+    let recorder = if features.record_interactions { Some(Arc::new(Recorder::from_env()?)) } else { None };
+    let http = Arc::new(match &recorder {
+        Some(recorder) => HttpClient::builder()
+            .token(token)
+            .proxy(recorder.clone().proxy().await?.to_string(), true)
+            .build(),
+        None => HttpClient::new(token),
+    });
+
+    let variants = Variants::load()?;
+    let commands = commands(&variants);
+
+    let cache = DefaultInMemoryCache::builder().resource_types(features.resource_types()).build();
+    let auto_responder = features.auto_respond.then(AutoResponder::from_env);
+    let media = if features.media_cache { Some(Arc::new(MediaCache::from_env()?)) } else { None };
+    let state = Arc::new(AppState::new(config, cache, auto_responder, media, variants)?);
+
+    // up first so probes get answers while starting, where `/readyz` says
+    // it isn't ready until the catalog is indexed
+    if let Some(addr) = state.config.server.addr {
+        server::serve(addr, state.clone()).await?;
+    }
 
     let interaction_client = {
         let user = http.current_user_application().await?.model().await?;
         http.interaction(user.id)
     };
+    interaction_client.set_global_commands(&commands).await?;
+
+    let nubs = get_nubs(&state.config).await?;
+    if let Some(media) = &state.media {
+        tokio::spawn(media.clone().prefetch(nubs.iter().map(|nub| nub.source.to_string()).collect()));
+    }
+    state.nub_finder.commit(nubs)?;
+
+    tokio::spawn({
+        let state = state.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                save(state.clone()).await;
+            }
+        }
+    });
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
-    interaction_client.set_global_commands(
-        &[
-            FourCommand::create_command().into(),
-            NubCommand::create_command().into(),
-            UnzipCommand::create_command().into(),
-            YouCommand::create_command().into(),
-            OilUpCommand::create_command().into(),
-            JumpCommand::create_command().into(),
-            SadPhoneCommand::create_command().into(),
-            HugeCommand::create_command().into(),
-            HelloCommand::create_command().into(),
-            RateRagebaitCommand::create_command().into(),
-        ]
-    ).await?;
-
-    let cache = DefaultInMemoryCache::builder().resource_types(ResourceType::MESSAGE).build();
-    let state = Arc::new(AppState::new()?);
-
-    state.nub_finder.commit(get_nubs().await?)?;
-
-    while let Some(item) = shard.next_event(EventTypeFlags::all()).await {
+    loop {
+        let item = tokio::select! {
+            item = shard.next_event(EventTypeFlags::all()) => item,
+            () = &mut shutdown => {
+                tracing::info!("shutting down");
+                break;
+            }
+        };
+        let Some(item) = item else {
+            break;
+        };
         let Ok(event) = item else {
             tracing::warn!(source = ?item.unwrap_err(), "error receiving event");
             continue;
         };
-        cache.update(&event);
+        state.cache.update(&event);
+        HEALTH.observe(&event, shard.state());
+
+        match &event {
+            Event::GatewayClose(_) => METRICS.gateway_reconnects.inc(),
+            Event::GatewayHeartbeatAck => {
+                if let Some(latency) = shard.latency().recent().first() {
+                    METRICS.shard_latency.set(latency.as_secs_f64());
+                }
+            }
+            _ => (),
+        }
 
-        tokio::spawn(handle_event(event, http.clone(), state.clone()));
+        if let Some(recorder) = &recorder
+            && let Event::InteractionCreate(interaction) = &event
+            && let Err(e) = recorder.record_interaction(&interaction.0)
+        {
+            tracing::warn!(error = e, "couldn't record interaction");
+        }
+
+        let span = logging::event_span(&event, shard.id());
+        tokio::spawn({
+            let (http, state) = (http.clone(), state.clone());
+            async move {
+                if let Err(e) = handle_event(event, http, state).await {
+                    tracing::warn!(error = e, "couldn't handle event");
+                }
+            }.instrument(span)
+        });
     }
 
+    save(state).await;
     Ok(())
 }
 
+/// Write out what's only saved every minute, rather than on every change.
+async fn save(state: Arc<AppState>) {
+    let saved = tokio::task::spawn_blocking(move || {
+        if let Err(e) = state.stats.flush() {
+            tracing::warn!(error = e, "failed to save stats");
+        }
+        if let Err(e) = state.nub_finder.flush_popularity() {
+            tracing::warn!(error = e, "failed to save popularity");
+        }
+    }).await;
+    if let Err(e) = saved {
+        tracing::warn!(error = %e, "failed to save");
+    }
+}
+
+/// Resolves on Ctrl-C, or when the process is asked to terminate.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "couldn't listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        () = terminate => (),
+    }
+}
+
+/// Every command we register, slash commands first, with their translations.
+fn commands(variants: &Variants) -> Vec<Command> {
+    let mut four_config = FourConfigCommand::create_command().into();
+    // only moderators see these, and they need every variant to pick from
+    variants.fill_choices(&mut four_config, Some("default-variant"), "variant", ContentRating::Nsfw);
+
+    let mut commands = vec![
+        FourCommand::create_command().into(),
+        FourOnCommand::create_command().into(),
+        NubCommand::create_command().into(),
+        NubCaptionCommand::create_command().into(),
+        NubFavoritesCommand::create_command().into(),
+        NubAddCommand::create_command().into(),
+        NubTagsExportCommand::create_command().into(),
+        UnzipCommand::create_command().into(),
+        YouCommand::create_command().into(),
+        OilUpCommand::create_command().into(),
+        JumpCommand::create_command().into(),
+        SadPhoneCommand::create_command().into(),
+        HugeCommand::create_command().into(),
+        HelloCommand::create_command().into(),
+        RateRagebaitCommand::create_command().into(),
+        four_config,
+        FourStatsCommand::create_command().into(),
+        context_menu::rate_ragebait_command(),
+        context_menu::reply_with_four_command(),
+        context_menu::nub_this_user_command(),
+    ];
+    commands.iter_mut().for_each(locale::localize);
+    commands
+}
+
 macro_rules! simple_command_handler {
     ($t:ident, $http:expr, $app_id:expr, $interaction_id:expr, $interaction_token:expr) => {
         $http.interaction($app_id).create_response(
@@ -86,71 +288,138 @@ macro_rules! simple_command_handler {
                         .build()
                 ),
             })
-        ).await?;
+        ).timed().await?;
     };
 }
 
+/// Handle an event, timing and counting interactions.
 async fn handle_event(
     event: Event,
     http: Arc<HttpClient>,
     state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Event::InteractionCreate(ic) = &event else {
+        return dispatch(event, http, state).await;
+    };
+
+    let (kind, command) = metrics::describe(&ic.0);
+    let started = Instant::now();
+    let result = dispatch(event, http, state).await;
+    METRICS.record_interaction((kind, &command), started.elapsed(), result.is_err());
+    result
+}
+
+async fn dispatch(
+    event: Event,
+    http: Arc<HttpClient>,
+    state: Arc<AppState>
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     match event {
         Event::InteractionCreate(ic) => {
-            let interaction = ic.0;
+            let mut interaction = ic.0;
 
             let app_id = interaction.application_id;
             let interaction_id = interaction.id;
 
-            if let Some(data) = interaction.data {
+            let (guild, channel) = state.location(&interaction);
+            tracing::debug!(?guild, ?channel, kind = ?interaction.kind, "interaction received");
+
+            if let Some(data) = interaction.data.take() {
                 let interaction_token = interaction.token.as_ref();
 
                 match data {
                     InteractionData::ApplicationCommand(mut cmd) => {
+                        let settings = interaction.guild_id
+                            .and_then(|id| state.settings.get(&id.get()))
+                            .unwrap_or_default();
+
+                        // autocomplete can't be answered with a message, so it's
+                        // only the command itself that gets turned away
+                        if
+                            interaction.kind == InteractionType::ApplicationCommand &&
+                            cmd.name != "four-config"
+                        {
+                            let locale = Locale::of(&interaction);
+                            let refusal = if !settings.allows_command(&cmd.name) {
+                                Some(locale.text("this command is disabled here"))
+                            } else if !settings.allows_channel(interaction.channel.as_ref().map(|c| c.id)) {
+                                Some(locale.text("i'm not allowed to post in this channel"))
+                            } else {
+                                None
+                            };
+
+                            if let Some(refusal) = refusal {
+                                http.interaction(app_id).create_response(
+                                    interaction_id,
+                                    interaction_token,
+                                    &(InteractionResponse {
+                                        kind: InteractionResponseType::ChannelMessageWithSource,
+                                        data: Some(
+                                            InteractionResponseDataBuilder::new()
+                                                .content(refusal)
+                                                .flags(MessageFlags::EPHEMERAL)
+                                                .build()
+                                        ),
+                                    })
+                                ).timed().await?;
+                                return Ok(());
+                            }
+                        }
+
+                        if interaction.kind == InteractionType::ApplicationCommand {
+                            state.stats.record_command(&cmd.name, interaction.guild_id);
+                        }
+
+                        if matches!(cmd.kind, CommandType::Message | CommandType::User) {
+                            return context_menu::handle_command(&interaction, *cmd, &http, state).await;
+                        }
+
                         match cmd.name.as_ref() {
                             "four" => {
-                                let four = match FourCommand::from_interaction((*cmd).into()) {
-                                    Ok(o) => o,
-                                    Err(e) => {
-                                        tracing::error!(?e);
-                                        return Err(e.into());
-                                    }
-                                };
-
-                                let variant = four.variant.unwrap_or_default();
-                                if matches!(variant, FourVariant::Song) {
+                                // variants are offered through autocomplete so edgy
+                                // ones only show up where they're allowed
+                                let max_rating = state.allowed_rating(&interaction);
+                                if let Some(typed) = cmd.options.iter().find_map(|opt| match &opt.value {
+                                    CommandOptionValue::Focused(typed, _) => Some(typed.clone()),
+                                    _ => None,
+                                }) {
                                     http.interaction(app_id).create_response(
                                         interaction_id,
-                                        &interaction_token,
+                                        interaction_token,
                                         &(InteractionResponse {
-                                            kind: InteractionResponseType::ChannelMessageWithSource,
+                                            kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
                                             data: Some(
                                                 InteractionResponseDataBuilder::new()
-                                                    .attachments([
-                                                        Attachment::from_bytes(
-                                                            "four.mp3".to_string(),
-                                                            FOUR_MP3.to_vec(),
-                                                            1
-                                                        ),
-                                                    ])
+                                                    .choices(state.variants.choices(max_rating, &typed))
                                                     .build()
                                             ),
                                         })
-                                    ).await?;
-                                } else {
-                                    http.interaction(app_id).create_response(
-                                        interaction_id,
-                                        &interaction_token,
-                                        &(InteractionResponse {
-                                            kind: InteractionResponseType::ChannelMessageWithSource,
-                                            data: Some(
-                                                InteractionResponseDataBuilder::new()
-                                                    .content(variant.url())
-                                                    .build()
-                                            ),
-                                        })
-                                    ).await?;
+                                    ).timed().await?;
+                                    return Ok(());
                                 }
+
+                                let four = match FourCommand::from_interaction((*cmd).into()) {
+                                    Ok(o) => o,
+                                    Err(e) => {
+                                        tracing::error!(?e);
+                                        return Err(e.into());
+                                    }
+                                };
+
+                                let variant = four.variant
+                                    .or(settings.default_variant)
+                                    .and_then(|value| state.variants.get(&value))
+                                    .filter(|variant| variant.rating <= max_rating)
+                                    .unwrap_or_else(|| state.variants.random(max_rating));
+                                state.stats.record_pick(format!("four:{}", variant.value), interaction.guild_id);
+                                http.interaction(app_id).create_response(
+                                    interaction_id,
+                                    interaction_token,
+                                    &(InteractionResponse {
+                                        kind: InteractionResponseType::ChannelMessageWithSource,
+                                        data: Some(variant.response(None)),
+                                    })
+                                ).timed().await?;
                             }
 
                             "nub" => {
@@ -159,114 +428,53 @@ async fn handle_event(
                                 match opt.value {
                                     // autocomplete
                                     CommandOptionValue::Focused(data, _) => {
-                                        if data.starts_with("nub:") {
+                                        nub_autocomplete(&interaction, data, &http, state).await?;
+                                    }
+
+                                    // finalized option
+                                    CommandOptionValue::String(data) => {
+                                        let locale = Locale::of(&interaction);
+                                        let buttons = buttons::nub_buttons(&data, Locale::of_guild(&interaction));
+                                        let picked = data.strip_prefix("nub:").map(str::to_string);
+                                        let Some(url) = find_nub(state.clone(), data, interaction.guild_id, state.allowed_rating(&interaction)).await? else {
                                             http.interaction(app_id).create_response(
                                                 interaction_id,
-                                                &interaction_token,
+                                                interaction_token,
                                                 &(InteractionResponse {
-                                                    kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+                                                    kind: InteractionResponseType::ChannelMessageWithSource,
                                                     data: Some(
                                                         InteractionResponseDataBuilder::new()
-                                                            .choices([
-                                                                CommandOptionChoice {
-                                                                    name: format!(
-                                                                        "shows image: {}",
-                                                                        &data
-                                                                            .split_once(":")
-                                                                            .unwrap().1
-                                                                    ),
-                                                                    name_localizations: None,
-                                                                    value: CommandOptionChoiceValue::String(
-                                                                        data
-                                                                    ),
-                                                                },
-                                                            ])
+                                                            .content(locale.text("i couldn't find that nub :("))
                                                             .build()
                                                     ),
                                                 })
-                                            ).await?;
+                                            ).timed().await?;
                                             return Ok(());
-                                        }
-                                        let results = tokio::task::spawn_blocking(move || {
-                                            state.nub_finder.search(&data)
-                                        }).await??;
-                                        http.interaction(app_id).create_response(
-                                            interaction_id,
-                                            &interaction_token,
-                                            &(InteractionResponse {
-                                                kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
-                                                data: Some(
-                                                    InteractionResponseDataBuilder::new()
-                                                        .choices(
-                                                            results
-                                                                .into_iter()
-                                                                .map(
-                                                                    |(
-                                                                        url,
-                                                                        keyword,
-                                                                    )| CommandOptionChoice {
-                                                                        name: keyword,
-                                                                        name_localizations: None,
-                                                                        value: CommandOptionChoiceValue::String(
-                                                                            format!("nub:{}", url)
-                                                                        ),
-                                                                    }
-                                                                )
-                                                                .take(25)
-                                                        )
-                                                        .build()
-                                                ),
-                                            })
-                                        ).await?;
-                                    }
-
-                                    // finalized option
-                                    CommandOptionValue::String(data) => {
-                                        let url = {
-                                            if data.starts_with("nub:") {
-                                                data.split_once(':').unwrap().1.to_string()
-                                            } else {
-                                                let results = tokio::task::spawn_blocking(move || {
-                                                    state.nub_finder.search(&data)
-                                                }).await??;
-                                                if let Some((url, _)) = results.first() {
-                                                    url.to_string()
-                                                } else {
-                                                    http.interaction(app_id).create_response(
-                                                        interaction_id,
-                                                        &interaction_token,
-                                                        &(InteractionResponse {
-                                                            kind: InteractionResponseType::ChannelMessageWithSource,
-                                                            data: Some(
-                                                                InteractionResponseDataBuilder::new()
-                                                                    .content(
-                                                                        "i couldn't find that nub :("
-                                                                    )
-                                                                    .build()
-                                                            ),
-                                                        })
-                                                    ).await?;
-                                                    return Ok(());
-                                                }
-                                            }
                                         };
+                                        state.stats.record_pick(format!("nub:{}", url), interaction.guild_id);
+                                        let (embed, attachment) = media::nub_embed(&state, &url).await?;
 
                                         http.interaction(app_id).create_response(
                                             interaction_id,
-                                            &interaction_token,
+                                            interaction_token,
                                             &(InteractionResponse {
                                                 kind: InteractionResponseType::ChannelMessageWithSource,
                                                 data: Some(
                                                     InteractionResponseDataBuilder::new()
-                                                        .embeds([
-                                                            EmbedBuilder::new()
-                                                                .image(ImageSource::url(url)?)
-                                                                .build(),
-                                                        ])
+                                                        .embeds([embed])
+                                                        .attachments(attachment)
+                                                        .components([buttons])
                                                         .build()
                                                 ),
                                             })
-                                        ).await?;
+                                        ).timed().await?;
+
+                                        // only autocomplete picks say which nub they wanted
+                                        if let Some(url) = picked {
+                                            tokio::task::spawn_blocking(move || {
+                                                state.nub_finder.record_selection(&url)
+                                            }).await??;
+                                        }
                                     }
 
                                     _ => (),
@@ -279,7 +487,7 @@ async fn handle_event(
                                     http,
                                     app_id,
                                     interaction_id,
-                                    &interaction_token
+                                    interaction_token
                                 );
                             }
 
@@ -289,7 +497,7 @@ async fn handle_event(
                                     http,
                                     app_id,
                                     interaction_id,
-                                    &interaction_token
+                                    interaction_token
                                 );
                             }
 
@@ -299,7 +507,7 @@ async fn handle_event(
                                     http,
                                     app_id,
                                     interaction_id,
-                                    &interaction_token
+                                    interaction_token
                                 );
                             }
 
@@ -309,7 +517,7 @@ async fn handle_event(
                                     http,
                                     app_id,
                                     interaction_id,
-                                    &interaction_token
+                                    interaction_token
                                 );
                             }
 
@@ -319,7 +527,7 @@ async fn handle_event(
                                     http,
                                     app_id,
                                     interaction_id,
-                                    &interaction_token
+                                    interaction_token
                                 );
                             }
 
@@ -329,7 +537,7 @@ async fn handle_event(
                                     http,
                                     app_id,
                                     interaction_id,
-                                    &interaction_token
+                                    interaction_token
                                 );
                             }
 
@@ -339,7 +547,7 @@ async fn handle_event(
                                     http,
                                     app_id,
                                     interaction_id,
-                                    &interaction_token
+                                    interaction_token
                                 );
                             }
 
@@ -351,9 +559,10 @@ async fn handle_event(
                                         return Err(e.into());
                                     }
                                 };
+                                state.stats.record_pick(format!("rate-ragebait:{}", rr.rating.value()), interaction.guild_id);
                                 http.interaction(app_id).create_response(
                                     interaction_id,
-                                    &interaction_token,
+                                    interaction_token,
                                     &(InteractionResponse {
                                         kind: InteractionResponseType::ChannelMessageWithSource,
                                         data: Some(
@@ -362,18 +571,102 @@ async fn handle_event(
                                                 .build()
                                         ),
                                     })
-                                ).await?;
+                                ).timed().await?;
+                            }
+
+                            "four-config" => {
+                                settings::handle_command(&interaction, *cmd, &http, state).await?;
+                            }
+
+                            "nub-favorites" => {
+                                favorites::handle_command(&interaction, &http, state).await?;
+                            }
+
+                            "four-stats" => {
+                                stats::handle_command(&interaction, *cmd, &http, state).await?;
+                            }
+
+                            "four-on" => {
+                                overlay::handle_command(&interaction, *cmd, &http, state).await?;
+                            }
+
+                            "nub-caption" => {
+                                let focused = cmd.options.iter().find_map(|opt| match &opt.value {
+                                    CommandOptionValue::Focused(data, _) => Some(data.clone()),
+                                    _ => None,
+                                });
+                                match focused {
+                                    Some(data) => nub_autocomplete(&interaction, data, &http, state).await?,
+                                    None => caption::handle_command(&interaction, *cmd, &http, state).await?,
+                                }
+                            }
+
+                            "nub-add" => {
+                                submissions::handle_command(&interaction, *cmd, &http, state).await?;
+                            }
+
+                            "nub-tags-export" => {
+                                tags::handle_export(&interaction, &http, state).await?;
                             }
 
                             _ => (),
                         }
                     }
+
+                    InteractionData::MessageComponent(component) => {
+                        let action = component.custom_id
+                            .split_once(':')
+                            .map_or(component.custom_id.as_str(), |(action, _)| action);
+
+                        state.stats.record_component(action, interaction.guild_id);
+
+                        match action {
+                            buttons::REROLL_ID | buttons::DELETE_ID => {
+                                buttons::handle_component(&interaction, *component, &http, state).await?;
+                            }
+                            favorites::FAVORITE_ID => {
+                                favorites::handle_component(&interaction, &http, state).await?;
+                            }
+                            tags::EDIT_ID => {
+                                tags::handle_component(&interaction, &http, state).await?;
+                            }
+                            submissions::APPROVE_ID | submissions::REJECT_ID => {
+                                submissions::handle_component(&interaction, *component, &http, state).await?;
+                            }
+                            _ => {
+                                context_menu::handle_component(
+                                    &interaction,
+                                    *component,
+                                    &http,
+                                    state
+                                ).await?;
+                            }
+                        }
+                    }
+
+                    InteractionData::ModalSubmit(modal) => {
+                        match modal.custom_id.as_str() {
+                            submissions::SUBMIT_ID => {
+                                submissions::handle_modal(&interaction, modal, &http, state).await?;
+                            }
+                            tags::EDIT_ID => {
+                                tags::handle_modal(&interaction, modal, &http, state).await?;
+                            }
+                            _ => {
+                                context_menu::handle_modal(&interaction, modal, &http, state).await?;
+                            }
+                        }
+                    }
+
                     _ => (),
                 }
             }
         }
+        Event::MessageCreate(message) => {
+            auto_respond::handle_message(message.0, http, state).await?;
+        }
         Event::Ready(_) => {
-            println!("Shard is ready");
+            tracing::info!("shard is ready");
         }
         _ => {}
     }
@@ -381,64 +674,203 @@ async fn handle_event(
     Ok(())
 }
 
-struct AppState {
-    nub_finder: NubFinder,
+/// Autocomplete choice names are capped at 100 characters.
+fn choice_name(mut name: String) -> String {
+    if let Some((end, _)) = name.char_indices().nth(100) {
+        name.truncate(end);
+    }
+    name
 }
 
-impl AppState {
-    fn new() -> Result<Self, Box<dyn core::error::Error + Send + Sync>> {
-        Ok(Self { nub_finder: NubFinder::new()? })
+/// Answer autocomplete for a nub option: favorites when empty, search
+/// results otherwise.
+async fn nub_autocomplete(
+    interaction: &Interaction,
+    data: String,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let app_id = interaction.application_id;
+    let interaction_id = interaction.id;
+    let interaction_token = interaction.token.as_str();
+
+    if data.starts_with("nub:") {
+        http.interaction(app_id).create_response(
+            interaction_id,
+            interaction_token,
+            &(InteractionResponse {
+                kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+                data: Some(
+                    InteractionResponseDataBuilder::new()
+                        .choices([
+                            CommandOptionChoice {
+                                name: Locale::of(interaction).format("shows image: {}", &[&data.split_once(":").unwrap().1]),
+                                name_localizations: None,
+                                value: CommandOptionChoiceValue::String(data),
+                            },
+                        ])
+                        .build()
+                ),
+            })
+        ).timed().await?;
+        return Ok(());
     }
-}
+    if data.trim().is_empty() {
+        let user_id = interaction.author_id();
+        let guild = interaction.guild_id.map(|id| id.get());
+        let max_rating = state.allowed_rating(interaction);
+        let favorites = tokio::task::spawn_blocking(move || {
+            match user_id {
+                Some(user_id) => favorites::choices(&state, user_id, guild, max_rating),
+                None => Ok(Vec::new()),
+            }
+        }).await??;
+        http.interaction(app_id).create_response(
+            interaction_id,
+            interaction_token,
+            &(InteractionResponse {
+                kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+                data: Some(
+                    InteractionResponseDataBuilder::new()
+                        .choices(favorites)
+                        .build()
+                ),
+            })
+        ).timed().await?;
+        return Ok(());
+    }
+    let guild = interaction.guild_id.map(|id| id.get());
+    let max_rating = state.allowed_rating(interaction);
+    let limit = state.config.search.autocomplete_limit;
+    let results = tokio::task::spawn_blocking(move || {
+        state.nub_finder.search(&data, guild, max_rating)
+    }).await??;
+    http.interaction(app_id).create_response(
+        interaction_id,
+        interaction_token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+            data: Some(
+                InteractionResponseDataBuilder::new()
+                    .choices(
+                        results
+                            .into_iter()
+                            .map(|(url, keyword)| CommandOptionChoice {
+                                name: choice_name(keyword),
+                                name_localizations: None,
+                                value: CommandOptionChoiceValue::String(format!("nub:{}", url)),
+                            })
+                            .take(limit)
+                    )
+                    .build()
+            ),
+        })
+    ).timed().await?;
 
-#[derive(CreateCommand, CommandModel)]
-#[command(name = "four", desc = "ball ball ball", contexts = "guild bot_dm private_channel")]
-struct FourCommand {
-    #[command(desc = "the variant to choose from. of course you love sillynubcat wdym")]
-    variant: Option<FourVariant>,
+    Ok(())
 }
 
-#[derive(CreateOption, CommandOption)]
-enum FourVariant {
-    #[option(name = "silly nub cat", value = "silly-nub")]
-    SillyNub,
+/// Resolve a `/nub` query to an image URL.
+///
+/// Values picked from autocomplete are prefixed with `nub:` and used as-is,
+/// anything else goes through the finder and takes the best match.
+async fn find_nub(
+    state: Arc<AppState>,
+    query: String,
+    guild_id: Option<Id<GuildMarker>>,
+    max_rating: ContentRating
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let guild = guild_id.map(|id| id.get());
+    if let Some(url) = query.strip_prefix("nub:") {
+        // typed by hand rather than picked, it could be anything in the catalog
+        let url = url.to_string();
+        let rating = tokio::task::spawn_blocking({
+            let url = url.clone();
+            move || state.nub_finder.rating(&url, guild)
+        }).await??;
+        return Ok(Some(url).filter(|_| rating.is_none_or(|rating| rating <= max_rating)));
+    }
 
-    #[option(name = "@sillynubnigga", value = "silly-nub-n")]
-    SillyNubN,
+    let results = tokio::task::spawn_blocking(move || {
+        state.nub_finder.search(&query, guild, max_rating)
+    }).await??;
+    Ok(results.into_iter().next().map(|(url, _)| url))
+}
 
-    #[option(name = "mc freakery", value = "mc-freakery")]
-    McFreakery,
+struct AppState {
+    nub_finder: NubFinder,
+    cache: DefaultInMemoryCache,
+    auto_responder: Option<AutoResponder>,
+    settings: SettingsStore,
+    stats: Stats,
+    favorites: FavoritesStore,
+    submissions: SubmissionStore,
+    media: Option<Arc<MediaCache>>,
+    variants: Variants,
+    config: Config,
+}
 
-    #[option(name = "george washington", value = "george-washington")]
-    GeorgeWashington,
+impl AppState {
+    fn new(
+        config: Config,
+        cache: DefaultInMemoryCache,
+        auto_responder: Option<AutoResponder>,
+        media: Option<Arc<MediaCache>>,
+        variants: Variants
+    ) -> Result<Self, Box<dyn core::error::Error + Send + Sync>> {
+        // stats.bin had every guild's counts together, with no way to split
+        // them up again
+        let old_stats = config.storage.path("stats.bin");
+        if old_stats.exists() {
+            tracing::info!(path = %old_stats.display(), "removing stats that weren't kept by guild");
+            std::fs::remove_file(&old_stats)?;
+        }
 
-    #[option(name = "black boi", value = "black-boi")]
-    BlackBoi,
+        Ok(Self {
+            nub_finder: NubFinder::new(&config)?,
+            cache,
+            auto_responder,
+            settings: SettingsStore::open(config.storage.path("settings.bin"))?,
+            stats: Stats::open(config.storage.path("guild_stats.bin"))?,
+            favorites: FavoritesStore::open(config.storage.path("favorites.bin"))?,
+            submissions: SubmissionStore::open(config.storage.path("submissions.bin"))?,
+            media,
+            variants,
+            config,
+        })
+    }
 
-    #[option(name = "+ song", value = "song")]
-    Song,
-}
+    /// The edgiest content that may be sent in reply to an interaction.
+    fn allowed_rating(&self, interaction: &Interaction) -> ContentRating {
+        let guild = interaction.guild_id
+            .and_then(|id| self.settings.get(&id.get()))
+            .and_then(|settings| settings.content_rating);
+        let nsfw = interaction.channel.as_ref().and_then(|channel| channel.nsfw).unwrap_or(false);
+        ContentRating::allowed(guild, interaction.guild_id.is_some(), nsfw)
+    }
 
-impl FourVariant {
-    /// Get the URL of this four.
-    const fn url(&self) -> &str {
-        match self {
-            Self::SillyNub =>
-                "https://tenor.com/view/nub-nub-cat-silly-cat-silly-kitty-gif-7773816275616110994",
-            Self::SillyNubN => "https://aweirddev.github.io/emojis/sillynubn.webp",
-            Self::BlackBoi => "https://tenor.com/view/four-gif-26151912",
-            Self::GeorgeWashington =>
-                "https://tenor.com/view/4-4-aura-4-finger-4-fingers-4-meme-gif-3022035956605345695",
-            Self::McFreakery => "https://aweirddev.github.io/emojis/four-mcfreakery.gif",
-            Self::Song => unreachable!(),
-        }
+    /// Returns: `(guild, channel)`, by name when cached and by ID otherwise.
+    fn location(&self, interaction: &Interaction) -> (Option<String>, Option<String>) {
+        let guild = interaction.guild_id.map(|id| {
+            self.cache.guild(id).map_or_else(|| id.to_string(), |guild| guild.name().to_string())
+        });
+        let channel = interaction.channel.as_ref().map(|channel| {
+            self.cache
+                .channel(channel.id)
+                .and_then(|cached| cached.name.clone())
+                .or_else(|| channel.name.clone())
+                .unwrap_or_else(|| channel.id.to_string())
+        });
+
+        (guild, channel)
     }
 }
 
-impl Default for FourVariant {
-    fn default() -> Self {
-        Self::SillyNub
-    }
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "four", desc = "ball ball ball", contexts = "guild bot_dm private_channel")]
+struct FourCommand {
+    #[command(desc = "the variant to choose from. of course you love sillynubcat wdym", autocomplete = true)]
+    variant: Option<String>,
 }
 
 #[derive(CreateCommand, CommandModel)]
@@ -521,9 +953,11 @@ struct RateRagebaitCommand {
     rating: RagebaitRating,
 }
 
+// registered choices show up everywhere, whatever the guild's rating, so
+// their names have to be safe
 #[derive(CreateOption, CommandOption)]
 enum RagebaitRating {
-    #[option(name = "1/10 (retarded)", value = "one")]
+    #[option(name = "1/10 (awful)", value = "one")]
     One,
 
     #[option(name = "7/10 (mid)", value = "seven")]
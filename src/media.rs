@@ -0,0 +1,181 @@
+//! Local copies of nub images, for when the hosts they link to are down.
+//!
+//! With `MEDIA_CACHE=1` every catalog nub is downloaded into `NUB_CACHE_DIR`
+//! at startup. Before sending a nub its URL is checked, and if it doesn't
+//! answer, the cached copy is uploaded as an attachment instead.
+
+use std::{ error::Error, path::PathBuf, sync::{ Arc, LazyLock }, time::{ Duration, Instant } };
+
+use dashmap::DashMap;
+use reqwest::Client;
+use tokio::task::JoinSet;
+use twilight_model::{ channel::message::Embed, http::attachment::Attachment };
+use twilight_util::builder::embed::{ EmbedBuilder, ImageSource };
+
+use crate::AppState;
+
+/// How long to wait for a host before treating it as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a check is trusted before the host is asked again.
+const CHECK_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Downloads in flight at once.
+const CONCURRENCY: usize = 16;
+
+/// Biggest image downloaded, in bytes.
+pub const MAX_DOWNLOAD_SIZE: u64 = 25 * 1024 * 1024;
+
+static CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+
+pub struct MediaCache {
+    dir: PathBuf,
+    /// URL → when it was last checked and whether it answered.
+    checked: DashMap<String, (Instant, bool)>,
+}
+
+impl MediaCache {
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Self::new(dotenvy::var("NUB_CACHE_DIR").unwrap_or_else(|_| "nub_cache".to_string()).into())
+    }
+
+    pub fn new(dir: PathBuf) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, checked: DashMap::new() })
+    }
+
+    /// Whether a URL has been downloaded already.
+    #[cfg(test)]
+    pub fn has(&self, url: &str) -> bool {
+        self.path(url).exists()
+    }
+
+    /// Read a nub from the cache, downloading it into the cache first if needed.
+    pub async fn fetch(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let path = self.path(url);
+        if let Ok(bytes) = std::fs::read(&path) {
+            return Ok(bytes);
+        }
+        let bytes = download(url).await?;
+        std::fs::write(path, &bytes)?;
+        Ok(bytes)
+    }
+
+    /// Download everything in `urls` that isn't cached yet.
+    pub async fn prefetch(self: Arc<Self>, urls: Vec<String>) {
+        let mut tasks = JoinSet::new();
+        for url in urls {
+            if self.path(&url).exists() {
+                continue;
+            }
+            if tasks.len() >= CONCURRENCY {
+                tasks.join_next().await;
+            }
+            let media = self.clone();
+            tasks.spawn(async move {
+                if let Err(e) = media.fetch(&url).await {
+                    tracing::warn!(url, ?e, "couldn't cache nub");
+                }
+            });
+        }
+        tasks.join_all().await;
+        tracing::info!("media cache is up to date");
+    }
+
+    /// An embed showing the nub, and the attachment it needs if the cached
+    /// copy is used.
+    ///
+    /// The original URL is kept as the embed's `url` so buttons can still tell
+    /// which nub it is.
+    pub async fn embed(
+        &self,
+        url: &str
+    ) -> Result<(Embed, Option<Attachment>), Box<dyn Error + Send + Sync>> {
+        let path = self.path(url);
+        if !path.exists() || self.is_up(url).await {
+            return Ok((EmbedBuilder::new().image(ImageSource::url(url)?).build(), None));
+        }
+
+        let bytes = std::fs::read(path)?;
+        let extension = url
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+            .filter(|extension| extension.len() <= 4 && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+            .unwrap_or("gif");
+        let filename = format!("nub.{}", extension);
+        tracing::debug!(url, "host is down, sending the cached copy");
+
+        Ok((
+            EmbedBuilder::new().url(url).image(ImageSource::attachment(&filename)?).build(),
+            Some(Attachment::from_bytes(filename, bytes, 1)),
+        ))
+    }
+
+    async fn is_up(&self, url: &str) -> bool {
+        if let Some(checked) = self.checked.get(url)
+            && checked.0.elapsed() < CHECK_TTL
+        {
+            return checked.1;
+        }
+
+        let up = CLIENT
+            .head(url)
+            .timeout(CHECK_TIMEOUT)
+            .send().await
+            .is_ok_and(|response| response.status().is_success());
+        self.checked.insert(url.to_string(), (Instant::now(), up));
+        up
+    }
+
+    /// Where a URL is cached, named so it's safe on any filesystem.
+    fn path(&self, url: &str) -> PathBuf {
+        let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+        self.dir.join(
+            url.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+                .collect::<String>()
+        )
+    }
+}
+
+/// An embed showing the nub, from the media cache if it's on.
+pub async fn nub_embed(
+    state: &AppState,
+    url: &str
+) -> Result<(Embed, Option<Attachment>), Box<dyn Error + Send + Sync>> {
+    match &state.media {
+        Some(media) => media.embed(url).await,
+        None => Ok((EmbedBuilder::new().image(ImageSource::url(url)?).build(), None)),
+    }
+}
+
+/// A nub's bytes, from the media cache if it's on.
+pub async fn nub_bytes(state: &AppState, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    match &state.media {
+        Some(media) => media.fetch(url).await,
+        None => download(url).await,
+    }
+}
+
+/// Download an image, giving up once it's over [`MAX_DOWNLOAD_SIZE`].
+pub async fn download(url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let too_big = || format!("{} is over {} bytes", url, MAX_DOWNLOAD_SIZE);
+
+    let mut response = CLIENT.get(url).send().await?.error_for_status()?;
+    if response.content_length().is_some_and(|length| length > MAX_DOWNLOAD_SIZE) {
+        return Err(too_big().into());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_DOWNLOAD_SIZE {
+            return Err(too_big().into());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// The nub shown in an embed, whether it links to it or carries a cached copy.
+pub fn shown_url(embed: &Embed) -> Option<String> {
+    embed.url.clone().or_else(|| embed.image.as_ref().map(|image| image.url.clone()))
+}
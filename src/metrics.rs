@@ -0,0 +1,172 @@
+//! Prometheus metrics, served at `/metrics` by [`crate::server`].
+//!
+//! They're always collected, since it's cheap, and only served when
+//! `server.addr` is set.
+
+use std::{ sync::{ LazyLock, Mutex }, time::{ Duration, SystemTime } };
+
+use prometheus::{
+    Gauge,
+    Histogram,
+    HistogramOpts,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+use twilight_model::application::interaction::{ Interaction, InteractionData, InteractionType };
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    interactions: IntCounterVec,
+    interaction_seconds: HistogramVec,
+    errors: IntCounterVec,
+    pub gateway_reconnects: IntCounter,
+    pub shard_latency: Gauge,
+    pub search_seconds: Histogram,
+    pub catalog_nubs: IntGauge,
+    catalog_age: Gauge,
+    /// When the catalog was downloaded, turned into `catalog_age` on render.
+    catalog_fetched: Mutex<Option<SystemTime>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let metrics = Self {
+            registry: Registry::new_custom(Some("four".to_string()), None).unwrap(),
+            interactions: IntCounterVec::new(
+                Opts::new("interactions_total", "Interactions handled, by kind and command."),
+                &["kind", "command"]
+            ).unwrap(),
+            interaction_seconds: HistogramVec::new(
+                HistogramOpts::new("interaction_duration_seconds", "Time taken to handle an interaction, by command."),
+                &["command"]
+            ).unwrap(),
+            errors: IntCounterVec::new(
+                Opts::new("handler_errors_total", "Interactions whose handler failed, by command."),
+                &["command"]
+            ).unwrap(),
+            gateway_reconnects: IntCounter::new("gateway_reconnects_total", "Gateway connections that closed.").unwrap(),
+            shard_latency: Gauge::new("shard_latency_seconds", "Latest gateway heartbeat round trip.").unwrap(),
+            search_seconds: Histogram::with_opts(
+                HistogramOpts::new("search_duration_seconds", "Time taken to search the catalog.")
+                    .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25])
+            ).unwrap(),
+            catalog_nubs: IntGauge::new("catalog_nubs", "Nubs in the search index, guild nubs included.").unwrap(),
+            catalog_age: Gauge::new("catalog_age_seconds", "Time since the catalog was downloaded.").unwrap(),
+            catalog_fetched: Mutex::new(None),
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.interactions.clone())).unwrap();
+        registry.register(Box::new(metrics.interaction_seconds.clone())).unwrap();
+        registry.register(Box::new(metrics.errors.clone())).unwrap();
+        registry.register(Box::new(metrics.gateway_reconnects.clone())).unwrap();
+        registry.register(Box::new(metrics.shard_latency.clone())).unwrap();
+        registry.register(Box::new(metrics.search_seconds.clone())).unwrap();
+        registry.register(Box::new(metrics.catalog_nubs.clone())).unwrap();
+        registry.register(Box::new(metrics.catalog_age.clone())).unwrap();
+
+        metrics
+    }
+
+    /// Count a handled interaction, as described by [`describe`].
+    pub fn record_interaction(&self, (kind, command): (&str, &str), elapsed: Duration, failed: bool) {
+        self.interactions.with_label_values(&[kind, command]).inc();
+        self.interaction_seconds.with_label_values(&[command]).observe(elapsed.as_secs_f64());
+        if failed {
+            self.errors.with_label_values(&[command]).inc();
+        }
+    }
+
+    pub fn set_catalog_fetched(&self, fetched: SystemTime) {
+        *self.catalog_fetched.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(fetched);
+    }
+
+    /// Time since the catalog was downloaded, once it's been loaded.
+    pub fn catalog_age(&self) -> Option<Duration> {
+        let fetched = *self.catalog_fetched.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        fetched.map(|fetched| fetched.elapsed().unwrap_or_default())
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        if let Some(age) = self.catalog_age() {
+            self.catalog_age.set(age.as_secs_f64());
+        }
+
+        TextEncoder::new().encode_to_string(&self.registry.gather()).unwrap_or_else(|e| {
+            tracing::warn!(?e, "couldn't encode metrics");
+            String::new()
+        })
+    }
+}
+
+/// Labels for an interaction: its kind, and the command it's for or the
+/// action of the component or modal it came from.
+///
+/// Returns: `(kind, command)`
+pub fn describe(interaction: &Interaction) -> (&'static str, String) {
+    let kind = match interaction.kind {
+        InteractionType::ApplicationCommand => "command",
+        InteractionType::ApplicationCommandAutocomplete => "autocomplete",
+        InteractionType::MessageComponent => "component",
+        InteractionType::ModalSubmit => "modal",
+        _ => "other",
+    };
+    let command = match &interaction.data {
+        Some(InteractionData::ApplicationCommand(cmd)) => cmd.name.clone(),
+        Some(InteractionData::MessageComponent(component)) => action(&component.custom_id),
+        Some(InteractionData::ModalSubmit(modal)) => action(&modal.custom_id),
+        _ => String::new(),
+    };
+
+    (kind, command)
+}
+
+/// The `<action>` of a `<action>:<payload>` custom ID, which unlike the
+/// payload is one of a few values.
+fn action(custom_id: &str) -> String {
+    custom_id.split_once(':').map_or(custom_id, |(action, _)| action).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{ buttons, tests::{ command, string, Harness } };
+
+    #[tokio::test]
+    async fn counts_interactions_and_searches() {
+        let harness = Harness::new().await;
+        let handled = || METRICS.interactions.with_label_values(&["command", "nub"]).get();
+        let (before, searches) = (handled(), METRICS.search_seconds.get_sample_count());
+
+        harness.respond(command("nub", json!([string("query", "sleepy")]))).await;
+
+        // other tests share the registry, so only check for at least one more
+        assert!(handled() > before);
+        assert!(METRICS.search_seconds.get_sample_count() > searches);
+        assert!(METRICS.interaction_seconds.with_label_values(&["nub"]).get_sample_count() > 0);
+
+        let rendered = METRICS.render();
+        for name in ["four_interactions_total", "four_interaction_duration_seconds", "four_catalog_nubs"] {
+            assert!(rendered.contains(name), "{} missing from {}", name, rendered);
+        }
+    }
+
+    #[test]
+    fn describes_components_by_action() {
+        let custom_id = format!("{}:sleepy", buttons::REROLL_ID);
+        let mut interaction = crate::tests::interaction(3, json!({ "custom_id": custom_id, "component_type": 2 }));
+        interaction["message"] = crate::tests::message("");
+        let interaction = serde_json::from_value::<Interaction>(interaction).unwrap();
+        assert_eq!(describe(&interaction), ("component", buttons::REROLL_ID.to_string()));
+    }
+}
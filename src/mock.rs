@@ -0,0 +1,178 @@
+//! A stand-in for the Discord API that records what the bot sends it.
+//!
+//! The HTTP client is pointed at it through twilight's proxy setting, so
+//! every request lands here instead of on Discord. Requests are answered with
+//! an empty JSON object and kept for inspection, except `GET`s of files added
+//! with [`MockDiscord::serve`], which stand in for images and other files
+//! on the web.
+
+use std::{ collections::HashMap, net::SocketAddr, sync::{ Arc, Mutex } };
+
+use tokio::{ net::{ TcpListener, TcpStream }, task::JoinHandle };
+use twilight_http::Client as HttpClient;
+
+use crate::server::{ find, read_request, write_response, MAX_API_BODY };
+
+/// Where twilight sends API requests, for [`twilight_http::API_VERSION`].
+const API_ROOT: &str = "api/v10/";
+
+/// A request the bot made, with any multipart body taken apart.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Relative to the API root, like `interactions/1/token/callback`.
+    pub path: String,
+    /// The JSON body, or the `payload_json` part of a multipart one.
+    pub body: serde_json::Value,
+    /// Uploaded files as `(filename, bytes)`.
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl Request {
+    /// Take apart a request read with [`read_request`]. `target` is relative
+    /// to the API root when it's under it.
+    pub fn new(method: String, target: &str, headers: &HashMap<String, String>, body: &[u8]) -> Self {
+        let target = target.trim_start_matches('/');
+        let content_type = headers.get("content-type").map_or("", String::as_str);
+        let (body, files) = match content_type.split_once("boundary=") {
+            Some((_, boundary)) => parse_multipart(body, boundary.trim_matches('"')),
+            None => (serde_json::from_slice(body).unwrap_or_default(), Vec::new()),
+        };
+
+        Self {
+            method,
+            path: target.strip_prefix(API_ROOT).unwrap_or(target).to_string(),
+            body,
+            files,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    requests: Vec<Request>,
+    files: HashMap<String, Vec<u8>>,
+}
+
+pub struct MockDiscord {
+    addr: SocketAddr,
+    shared: Arc<Mutex<Shared>>,
+    server: JoinHandle<()>,
+}
+
+impl MockDiscord {
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Mutex::new(Shared::default()));
+
+        let server = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(handle(stream, shared.clone()));
+                }
+            }
+        });
+
+        Ok(Self { addr, shared, server })
+    }
+
+    /// An HTTP client that talks to this server instead of Discord.
+    pub fn client(&self) -> HttpClient {
+        HttpClient::builder()
+            .proxy(self.addr.to_string(), true)
+            .ratelimiter(None)
+            .token("mock-token".to_string())
+            .build()
+    }
+
+    /// Where the server is, like `http://127.0.0.1:1234`.
+    #[cfg(test)]
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Serve `bytes` at `path`. Returns: the URL to download them from.
+    #[cfg(test)]
+    pub fn serve(&self, path: &str, bytes: Vec<u8>) -> String {
+        let path = path.trim_start_matches('/').to_string();
+        let url = format!("{}/{}", self.url(), path);
+        self.shared.lock().unwrap().files.insert(path, bytes);
+        url
+    }
+
+    /// Everything requested since the last call, oldest first.
+    pub fn take(&self) -> Vec<Request> {
+        std::mem::take(&mut self.shared.lock().unwrap().requests)
+    }
+}
+
+impl Drop for MockDiscord {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Read one request, record or serve it, and close the connection.
+async fn handle(mut stream: TcpStream, shared: Arc<Mutex<Shared>>) {
+    let Ok((method, target, headers, body)) = read_request(&mut stream, MAX_API_BODY).await else {
+        return;
+    };
+    let path = target.trim_start_matches('/');
+
+    let (status, content_type, response) = match (method.as_str(), path.starts_with(API_ROOT)) {
+        ("GET", false) => match shared.lock().unwrap().files.get(path) {
+            Some(bytes) => ("200 OK", "application/octet-stream", bytes.clone()),
+            None => ("404 Not Found", "text/plain", b"not found".to_vec()),
+        },
+        _ => {
+            shared.lock().unwrap().requests.push(Request::new(method, &target, &headers, &body));
+            ("200 OK", "application/json", b"{}".to_vec())
+        }
+    };
+
+    write_response(&mut stream, status, content_type, &response).await;
+}
+
+/// Split a `multipart/form-data` body into its JSON payload and files.
+fn parse_multipart(body: &[u8], boundary: &str) -> (serde_json::Value, Vec<(String, Vec<u8>)>) {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut payload = serde_json::Value::Null;
+    let mut files = Vec::new();
+
+    let mut rest = body;
+    while let Some(start) = find(rest, &delimiter) {
+        rest = &rest[start + delimiter.len()..];
+        let Some(end) = find(rest, &delimiter) else {
+            break;
+        };
+        let part = &rest[..end];
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+
+        let Some(head_end) = find(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let head = String::from_utf8_lossy(&part[..head_end]);
+        let content = &part[head_end + 4..];
+
+        match disposition(&head, "filename") {
+            Some(filename) => files.push((filename, content.to_vec())),
+            None if disposition(&head, "name").as_deref() == Some("payload_json") => {
+                payload = serde_json::from_slice(content).unwrap_or_default();
+            }
+            None => (),
+        }
+    }
+
+    (payload, files)
+}
+
+/// A quoted `key="value"` from a part's `Content-Disposition`.
+fn disposition(head: &str, key: &str) -> Option<String> {
+    let pattern = format!(" {}=\"", key);
+    let start = head.find(&pattern)? + pattern.len();
+    let end = head[start..].find('"')?;
+    Some(head[start..start + end].to_string())
+}
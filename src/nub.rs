@@ -1,111 +1,889 @@
+use std::{ collections::{ HashMap, HashSet }, path::Path, sync::Mutex, time::SystemTime };
+
+use rand::seq::IteratorRandom;
 use rkyv::{ rancor, Archive };
 use reqwest::Client;
 use tantivy::{
-    collector::TopDocs,
+    collector::{ DocSetCollector, TopDocs },
     doc,
-    query::QueryParser,
-    schema::{ self, Value },
+    query::{ AllQuery, BooleanQuery, ConstScoreQuery, Occur, Query, QueryParser, TermQuery },
+    schema::{ self, IndexRecordOption, Value },
     DocAddress,
+    DocId,
     Index,
     IndexWriter,
     Score,
+    Searcher,
+    SegmentReader,
     TantivyDocument,
+    Term,
 };
 
-const NUB_ENDPOINT: &str = "https://solanapulseserver-production.up.railway.app/memeslist";
+use crate::{ config::Config, metrics::METRICS, rating::ContentRating, store::Store };
+
+/// How much popularity weighs against text relevance. A nub picked `n` times
+/// has its score multiplied by `1 + POPULARITY_BOOST * ln(1 + n)`.
+const POPULARITY_BOOST: f32 = 0.25;
+
+/// `guild` of nubs from the global catalog.
+pub const GLOBAL: u64 = 0;
+
+/// Tag edits made by moderators, by guild ([`GLOBAL`] for the catalog's nubs)
+/// and URL.
+pub type TagOverrides = Store<(u64, String), TagOverride>;
 
-#[derive(Debug, Archive, rkyv::Serialize, rkyv::Deserialize, serde::Deserialize)]
+/// Ratings set by moderators, by guild and URL. Other nubs are rated by their
+/// tags.
+pub type Ratings = Store<(u64, String), ContentRating>;
+
+#[derive(Debug, Clone, Archive, rkyv::Serialize, rkyv::Deserialize, serde::Serialize, serde::Deserialize)]
 pub struct Nub {
     pub source: Box<str>,
     pub tags: Vec<Box<str>>,
 }
 
-pub async fn fetch_nubs() -> Result<Vec<Nub>, Box<dyn core::error::Error + Send + Sync>> {
+/// Local changes to a nub's tags, applied on top of the catalog.
+#[derive(Debug, Clone, Default, Archive, rkyv::Serialize, rkyv::Deserialize, serde::Serialize)]
+pub struct TagOverride {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl TagOverride {
+    /// The tags with these edits made to them.
+    pub fn apply(&self, tags: &[Box<str>]) -> Vec<String> {
+        let mut tags = tags
+            .iter()
+            .map(|tag| tag.to_string())
+            .filter(|tag| !self.removed.contains(tag))
+            .collect::<Vec<_>>();
+        for tag in &self.added {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        tags
+    }
+}
+
+/// Who's editing a nub. A guild can only edit the nubs added to it, and the
+/// catalog's owner can edit any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Editor {
+    Owner,
+    Guild(u64),
+}
+
+/// How editing a nub went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edited {
+    Saved,
+    Missing,
+    Forbidden,
+}
+
+pub async fn fetch_nubs(endpoint: &str) -> Result<Vec<Nub>, Box<dyn core::error::Error + Send + Sync>> {
     let client = Client::new();
-    Ok(client.get(NUB_ENDPOINT).send().await?.json::<Vec<Nub>>().await?)
+    Ok(client.get(endpoint).send().await?.error_for_status()?.json::<Vec<Nub>>().await?)
 }
 
-pub fn save_nubs(nubs: &Vec<Nub>) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
-    std::fs::write("four.bin", rkyv::to_bytes::<rancor::Error>(nubs)?)?;
+pub fn save_nubs(path: &Path, nubs: &Vec<Nub>) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
+    std::fs::write(path, rkyv::to_bytes::<rancor::Error>(nubs)?)?;
     Ok(())
 }
 
-pub fn load_nubs() -> Result<Vec<Nub>, Box<dyn core::error::Error + Send + Sync>> {
-    Ok(rkyv::from_bytes::<Vec<Nub>, rancor::Error>(&std::fs::read("four.bin")?)?)
+pub fn load_nubs(path: &Path) -> Result<Vec<Nub>, Box<dyn core::error::Error + Send + Sync>> {
+    Ok(rkyv::from_bytes::<Vec<Nub>, rancor::Error>(&std::fs::read(path)?)?)
+}
+
+pub async fn get_nubs(config: &Config) -> Result<Vec<Nub>, Box<dyn core::error::Error + Send + Sync>> {
+    let path = &config.catalog.path;
+    if std::fs::exists(path)? {
+        match load_nubs(path) {
+            Ok(nubs) => {
+                if let Ok(modified) = std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+                    METRICS.set_catalog_fetched(modified);
+                }
+                return Ok(nubs);
+            }
+            // it's only a copy, so a damaged one is downloaded again
+            Err(e) => tracing::warn!(?e, path = %path.display(), "saved catalog is unreadable, fetching it again"),
+        }
+    }
+
+    let nubs = fetch_nubs(&config.catalog.endpoint).await?;
+    save_nubs(path, &nubs)?;
+    METRICS.set_catalog_fetched(SystemTime::now());
+    Ok(nubs)
 }
 
-pub async fn get_nubs() -> Result<Vec<Nub>, Box<dyn core::error::Error + Send + Sync>> {
-    if std::fs::exists("four.bin")? {
-        load_nubs()
-    } else {
-        let nubs = fetch_nubs().await?;
-        save_nubs(&nubs)?;
-        Ok(nubs)
+/// Open the tag overrides, moving in the ones `tag_overrides.bin` kept by
+/// URL alone, from before guilds could each add the same URL.
+pub fn open_tag_overrides(config: &Config) -> Result<TagOverrides, Box<dyn core::error::Error + Send + Sync>> {
+    let tag_overrides = TagOverrides::open(config.storage.path("nub_tag_overrides.bin"))?;
+    let old = config.storage.path("tag_overrides.bin");
+    if std::fs::exists(&old)? {
+        let guilds = guilds_by_url(config)?;
+        for (url, tag_override) in Store::<String, TagOverride>::open(&old)?.entries() {
+            let guild = guilds.get(&url).copied().unwrap_or(GLOBAL);
+            tag_overrides.update((guild, url), |current| *current = tag_override);
+        }
+        tag_overrides.flush()?;
+        std::fs::remove_file(&old)?;
     }
+    Ok(tag_overrides)
+}
+
+/// Open the ratings, moving in the ones `ratings.bin` kept by URL alone.
+pub fn open_ratings(config: &Config) -> Result<Ratings, Box<dyn core::error::Error + Send + Sync>> {
+    let ratings = Ratings::open(config.storage.path("nub_ratings.bin"))?;
+    let old = config.storage.path("ratings.bin");
+    if std::fs::exists(&old)? {
+        let guilds = guilds_by_url(config)?;
+        for (url, rating) in Store::<String, ContentRating>::open(&old)?.entries() {
+            let guild = guilds.get(&url).copied().unwrap_or(GLOBAL);
+            ratings.update((guild, url), |current| *current = rating);
+        }
+        ratings.flush()?;
+        std::fs::remove_file(&old)?;
+    }
+    Ok(ratings)
+}
+
+/// Which guild each guild nub was added to, by URL.
+fn guilds_by_url(config: &Config) -> Result<HashMap<String, u64>, Box<dyn core::error::Error + Send + Sync>> {
+    let guild_nubs = Store::<u64, Vec<Nub>>::open(config.storage.path("guild_nubs.bin"))?;
+    Ok(guild_nubs
+        .entries()
+        .into_iter()
+        .flat_map(|(guild, nubs)| nubs.into_iter().map(move |nub| (nub.source.to_string(), guild)))
+        .collect())
 }
 
 pub struct NubFinder {
     index: Index,
-    fields: (schema::Field, schema::Field),
+    writer: Mutex<IndexWriter>,
+    /// `(url, keywords, popularity, guild, rating)`
+    fields: (schema::Field, schema::Field, schema::Field, schema::Field, schema::Field),
+    /// How often each nub was picked from autocomplete, by URL.
+    popularity: Store<String, u64>,
+    /// URLs picked since the last [`NubFinder::flush_popularity`], whose
+    /// indexed popularity is behind.
+    picked: Mutex<HashSet<String>>,
+    /// Nubs members added to a single guild, by guild ID.
+    guild_nubs: Store<u64, Vec<Nub>>,
+    tag_overrides: TagOverrides,
+    ratings: Ratings,
+    /// Most results a search returns.
+    limit: usize,
 }
 
 impl NubFinder {
-    pub fn new() -> Result<Self, Box<dyn core::error::Error + Send + Sync>> {
+    pub fn new(config: &Config) -> Result<Self, Box<dyn core::error::Error + Send + Sync>> {
         let mut builder = schema::Schema::builder();
         let field_url = builder.add_text_field("url", schema::STRING | schema::STORED);
         let field_keywords = builder.add_text_field("keywords", schema::TEXT | schema::STORED);
+        let field_popularity = builder.add_u64_field("popularity", schema::FAST);
+        let field_guild = builder.add_u64_field("guild", schema::INDEXED | schema::STORED);
+        let field_rating = builder.add_u64_field("rating", schema::INDEXED | schema::STORED);
         let schema = builder.build();
 
         let index = Index::create_in_ram(schema.clone());
+        let writer = Mutex::new(index.writer(config.search.writer_heap)?);
 
-        Ok(Self { index, fields: (field_url, field_keywords) })
+        Ok(Self {
+            index,
+            writer,
+            fields: (field_url, field_keywords, field_popularity, field_guild, field_rating),
+            popularity: Store::open(config.storage.path("popularity.bin"))?,
+            picked: Mutex::new(HashSet::new()),
+            guild_nubs: Store::open(config.storage.path("guild_nubs.bin"))?,
+            tag_overrides: open_tag_overrides(config)?,
+            ratings: open_ratings(config)?,
+            limit: config.search.limit,
+        })
     }
 
+    /// Replace the indexed catalog with `nubs`, keeping the guild nubs and
+    /// applying the tag overrides.
     pub fn commit(&self, nubs: Vec<Nub>) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
-        let mut index_writer: IndexWriter = self.index.writer(100_000_000)?;
+        let mut index_writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        index_writer.delete_all_documents()?;
+        let mut count = nubs.len();
         for nub in nubs {
-            let source = nub.source.as_bytes();
-            let keywords_str = nub.tags.join(", ");
-
-            index_writer.add_document(
-                doc!(
-                    self.fields.0 => source,
-                    self.fields.1 => keywords_str
-                )
-            )?;
+            index_writer.add_document(self.document(&nub.source, &self.keywords(&nub, GLOBAL), GLOBAL))?;
+        }
+        for (guild, nubs) in self.guild_nubs.entries() {
+            count += nubs.len();
+            for nub in nubs {
+                index_writer.add_document(self.document(&nub.source, &self.keywords(&nub, guild), guild))?;
+            }
         }
         index_writer.commit()?;
+        METRICS.catalog_nubs.set(count as i64);
 
         Ok(())
     }
 
+    /// How many nubs are searchable, guild nubs included.
+    pub fn num_docs(&self) -> Result<u64, Box<dyn core::error::Error + Send + Sync>> {
+        Ok(self.index.reader()?.searcher().num_docs())
+    }
+
+    /// Add a nub only searchable from the given guild.
+    ///
+    /// One that's already searchable from the guild isn't added again, but
+    /// other guilds can add their own copy of it.
+    pub fn add_guild_nub(
+        &self,
+        guild: u64,
+        nub: Nub
+    ) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
+        if self.indexed(&nub.source, Some(guild))?.is_some() {
+            return Err(format!("{} is already a nub", nub.source).into());
+        }
+        {
+            let mut index_writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            index_writer.add_document(self.document(&nub.source, &self.keywords(&nub, guild), guild))?;
+            index_writer.commit()?;
+        }
+        METRICS.catalog_nubs.inc();
+
+        self.guild_nubs.update(guild, |nubs| nubs.push(nub));
+        self.guild_nubs.flush()
+    }
+
+    /// Look a nub up by its URL, in the global catalog and among the nubs
+    /// added to `guild` if given.
+    ///
+    /// Returns: `(url, keywords)`
+    pub fn get(
+        &self,
+        url: &str,
+        guild: Option<u64>
+    ) -> Result<Option<(String, String)>, Box<dyn core::error::Error + Send + Sync>> {
+        Ok(self.indexed(url, guild)?.map(|(keywords, _)| (url.to_string(), keywords)))
+    }
+
+    /// Count a nub being picked so it ranks higher once
+    /// [`NubFinder::flush_popularity`] runs.
+    pub fn record_selection(&self, url: &str) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
+        // a made-up `nub:` value isn't in the catalog, so there's nothing to rank
+        if self.copies(url)?.is_empty() {
+            return Ok(());
+        }
+
+        self.popularity.update(url.to_string(), |count| {
+            *count += 1;
+        });
+        self.picked.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(url.to_string());
+        Ok(())
+    }
+
+    /// Reindex the nubs picked since last time with their new popularity,
+    /// in a single commit, and save the counts.
+    pub fn flush_popularity(&self) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
+        let picked = std::mem::take(&mut *self.picked.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        if picked.is_empty() {
+            return Ok(());
+        }
+
+        {
+            let mut index_writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for url in &picked {
+                // every guild's copy shares the count
+                let copies = self.copies(url)?;
+                index_writer.delete_term(Term::from_field_text(self.fields.0, url));
+                for (keywords, guild) in copies {
+                    index_writer.add_document(self.document(url, &keywords, guild))?;
+                }
+            }
+            index_writer.commit()?;
+        }
+
+        self.popularity.flush()
+    }
+
+    /// Replace the tags of a nub, as seen from `guild`, remembering the
+    /// difference from what it had as an override so it survives the catalog
+    /// being reloaded.
+    pub fn edit_tags(
+        &self,
+        url: &str,
+        tags: Vec<String>,
+        guild: Option<u64>,
+        editor: Editor
+    ) -> Result<Edited, Box<dyn core::error::Error + Send + Sync>> {
+        let (keywords, guild) = match self.editable(url, guild, editor)? {
+            Ok(indexed) => indexed,
+            Err(refused) => return Ok(refused),
+        };
+        let old = split_keywords(&keywords);
+
+        self.tag_overrides.update((guild, url.to_string()), |tag_override| {
+            for tag in tags.iter().filter(|tag| !old.contains(tag)) {
+                tag_override.removed.retain(|removed| removed != tag);
+                if !tag_override.added.contains(tag) {
+                    tag_override.added.push(tag.clone());
+                }
+            }
+            for tag in old.iter().filter(|tag| !tags.contains(tag)) {
+                tag_override.added.retain(|added| added != tag);
+                if !tag_override.removed.contains(tag) {
+                    tag_override.removed.push(tag.clone());
+                }
+            }
+        });
+        self.reindex(url, &tags.join(", "), guild)?;
+
+        self.tag_overrides.flush()?;
+        Ok(Edited::Saved)
+    }
+
+    /// Rate a nub, as seen from `guild`, overriding the rating its tags give
+    /// it. Rating it what its tags give it drops the override, so it follows
+    /// later tag edits again.
+    pub fn set_rating(
+        &self,
+        url: &str,
+        rating: ContentRating,
+        guild: Option<u64>,
+        editor: Editor
+    ) -> Result<Edited, Box<dyn core::error::Error + Send + Sync>> {
+        let (keywords, guild) = match self.editable(url, guild, editor)? {
+            Ok(indexed) => indexed,
+            Err(refused) => return Ok(refused),
+        };
+
+        let key = (guild, url.to_string());
+        if rating == ContentRating::from_tags(&split_keywords(&keywords)) {
+            self.ratings.remove(&key);
+        } else {
+            self.ratings.update(key, |current| {
+                *current = rating;
+            });
+        }
+        self.reindex(url, &keywords, guild)?;
+
+        self.ratings.flush()?;
+        Ok(Edited::Saved)
+    }
+
+    /// How a nub is rated as seen from `guild`, if it's indexed. One only
+    /// other guilds added is rated the strictest any of them rates it, so it
+    /// never shows up somewhere it's too edgy for.
+    pub fn rating(
+        &self,
+        url: &str,
+        guild: Option<u64>
+    ) -> Result<Option<ContentRating>, Box<dyn core::error::Error + Send + Sync>> {
+        if let Some((keywords, guild)) = self.indexed(url, guild)? {
+            return Ok(Some(self.rating_for(url, &keywords, guild)));
+        }
+        Ok(self.copies(url)?
+            .into_iter()
+            .map(|(keywords, guild)| self.rating_for(url, &keywords, guild))
+            .max())
+    }
+
+    /// Every tag override, by guild and URL.
+    pub fn tag_overrides(&self) -> Vec<((u64, String), TagOverride)> {
+        let mut overrides = self.tag_overrides
+            .entries()
+            .into_iter()
+            .filter(|(_, tag_override)| {
+                !tag_override.added.is_empty() || !tag_override.removed.is_empty()
+            })
+            .collect::<Vec<_>>();
+        overrides.sort_by(|(a, _), (b, _)| a.cmp(b));
+        overrides
+    }
+
+    /// Search the global catalog, plus the nubs added to `guild` if given,
+    /// among nubs rated at most `max_rating`.
+    ///
     /// Returns: `(url, keywords)`
     pub fn search(
         &self,
-        q: &str
+        q: &str,
+        guild: Option<u64>,
+        max_rating: ContentRating
     ) -> Result<Vec<(String, String)>, Box<dyn core::error::Error + Send + Sync>> {
+        let _timer = METRICS.search_seconds.start_timer();
         let reader = self.index.reader()?;
         let searcher = reader.searcher();
         let query_parser = QueryParser::for_index(&self.index, vec![self.fields.0, self.fields.1]);
-        let query = query_parser.parse_query(q)?;
+        // people type anything into autocomplete, so stray quotes or colons
+        // are searched for as well as they can be instead of failing
+        let (query, _) = query_parser.parse_query_lenient(q);
+        let query = self.scoped(query, guild, max_rating);
+
+        let collector = TopDocs::with_limit(self.limit).tweak_score(|segment_reader: &SegmentReader| {
+            let popularity = segment_reader.fast_fields().u64("popularity").ok();
+
+            move |doc: DocId, score: Score| {
+                let picks = popularity
+                    .as_ref()
+                    .and_then(|column| column.first(doc))
+                    .unwrap_or(0);
+                score * (1.0 + POPULARITY_BOOST * (picks as f32).ln_1p())
+            }
+        });
 
-        let top_docs: Vec<(Score, DocAddress)> = searcher.search(&query, &TopDocs::with_limit(10))?;
+        let top_docs: Vec<(Score, DocAddress)> = searcher.search(&query, &collector)?;
         let mut results = Vec::new();
 
         for (_score, doc_address) in top_docs {
+            if let Some(result) = self.read(&searcher, doc_address)? {
+                results.push(result);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Pick a random nub, or a random one among the matches for `q`.
+    ///
+    /// Returns: `(url, keywords)`
+    pub fn random(
+        &self,
+        q: Option<&str>,
+        guild: Option<u64>,
+        max_rating: ContentRating
+    ) -> Result<Option<(String, String)>, Box<dyn core::error::Error + Send + Sync>> {
+        if let Some(q) = q {
+            return Ok(self.search(q, guild, max_rating)?.into_iter().choose(&mut rand::rng()));
+        }
+
+        let reader = self.index.reader()?;
+        let searcher = reader.searcher();
+        let docs = searcher.search(&self.scoped(Box::new(AllQuery), guild, max_rating), &DocSetCollector)?;
+
+        match docs.into_iter().choose(&mut rand::rng()) {
+            Some(doc_address) => self.read(&searcher, doc_address),
+            None => Ok(None),
+        }
+    }
+
+    /// Limit `query` to the global catalog and the given guild's nubs, rated
+    /// at most `max_rating`.
+    fn scoped(&self, query: Box<dyn Query>, guild: Option<u64>, max_rating: ContentRating) -> BooleanQuery {
+        let any_of = |field: schema::Field, values: Vec<u64>| -> (Occur, Box<dyn Query>) {
+            let terms = values
+                .into_iter()
+                .map(|value| -> (Occur, Box<dyn Query>) {
+                    (
+                        Occur::Should,
+                        Box::new(
+                            TermQuery::new(Term::from_field_u64(field, value), IndexRecordOption::Basic)
+                        ),
+                    )
+                })
+                .collect();
+            // only there to filter, so it shouldn't move the score
+            (Occur::Must, Box::new(ConstScoreQuery::new(Box::new(BooleanQuery::new(terms)), 0.0)))
+        };
+        let mut guilds = vec![GLOBAL];
+        guilds.extend(guild);
+        let ratings = ContentRating::ALL
+            .into_iter()
+            .filter(|rating| *rating <= max_rating)
+            .map(ContentRating::index)
+            .collect();
+
+        BooleanQuery::new(
+            vec![
+                (Occur::Must, query),
+                any_of(self.fields.3, guilds),
+                any_of(self.fields.4, ratings),
+            ]
+        )
+    }
+
+    /// Returns: `(keywords, guild)` of the nub with this URL in the global
+    /// catalog or among the nubs added to `guild`
+    fn indexed(
+        &self,
+        url: &str,
+        guild: Option<u64>
+    ) -> Result<Option<(String, u64)>, Box<dyn core::error::Error + Send + Sync>> {
+        let copies = self.copies(url)?;
+        // the guild's own copy wins, in case the catalog took the URL in later
+        Ok(copies
+            .iter()
+            .find(|(_, copy)| Some(*copy) == guild)
+            .or_else(|| copies.iter().find(|(_, copy)| *copy == GLOBAL))
+            .cloned())
+    }
+
+    /// Returns: `(keywords, guild)` of every indexed nub with this URL, one
+    /// per guild it was added to
+    fn copies(&self, url: &str) -> Result<Vec<(String, u64)>, Box<dyn core::error::Error + Send + Sync>> {
+        let reader = self.index.reader()?;
+        let searcher = reader.searcher();
+        let query = TermQuery::new(Term::from_field_text(self.fields.0, url), IndexRecordOption::Basic);
+
+        let mut copies = Vec::new();
+        for doc_address in searcher.search(&query, &DocSetCollector)? {
             let doc = searcher.doc::<TantivyDocument>(doc_address)?;
-            if let Some(url_value) = doc.get_first(self.fields.0) {
-                let value = url_value.as_value();
-                let url = String::from_utf8_lossy(value.as_bytes().unwrap()).into_owned();
+            let keywords = doc.get_first(self.fields.1).and_then(|value| value.as_value().as_str().map(str::to_owned));
+            let guild = doc
+                .get_first(self.fields.3)
+                .and_then(|value| value.as_value().as_u64())
+                .unwrap_or(GLOBAL);
+            copies.extend(keywords.map(|keywords| (keywords, guild)));
+        }
+        Ok(copies)
+    }
 
-                if let Some(kw_value) = doc.get_first(self.fields.1) {
-                    let value = kw_value.as_value();
-                    let kw = value.as_str().unwrap().to_owned();
+    /// The indexed nub, if `editor` may edit it.
+    ///
+    /// Returns: `(keywords, guild)`, or why it can't be edited
+    fn editable(
+        &self,
+        url: &str,
+        guild: Option<u64>,
+        editor: Editor
+    ) -> Result<Result<(String, u64), Edited>, Box<dyn core::error::Error + Send + Sync>> {
+        Ok(match self.indexed(url, guild)? {
+            None => Err(Edited::Missing),
+            Some((_, guild)) if editor != Editor::Owner && editor != Editor::Guild(guild) => Err(Edited::Forbidden),
+            Some(indexed) => Ok(indexed),
+        })
+    }
 
-                    results.push((url, kw));
-                }
-            }
+    fn reindex(
+        &self,
+        url: &str,
+        keywords: &str,
+        guild: u64
+    ) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
+        let mut index_writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // other guilds' copies stay as they are
+        index_writer.delete_query(
+            Box::new(
+                BooleanQuery::intersection(
+                    vec![
+                        Box::new(TermQuery::new(Term::from_field_text(self.fields.0, url), IndexRecordOption::Basic)),
+                        Box::new(TermQuery::new(Term::from_field_u64(self.fields.3, guild), IndexRecordOption::Basic))
+                    ]
+                )
+            )
+        )?;
+        index_writer.add_document(self.document(url, keywords, guild))?;
+        index_writer.commit()?;
+        Ok(())
+    }
+
+    /// The nub's tags with the guild's override applied, as indexed.
+    fn keywords(&self, nub: &Nub, guild: u64) -> String {
+        match self.tag_overrides.get(&(guild, nub.source.to_string())) {
+            Some(tag_override) => tag_override.apply(&nub.tags).join(", "),
+            None => nub.tags.join(", "),
         }
-        Ok(results)
+    }
+
+    fn document(&self, url: &str, keywords: &str, guild: u64) -> TantivyDocument {
+        doc!(
+            self.fields.0 => url,
+            self.fields.1 => keywords,
+            self.fields.2 => self.popularity.get(&url.to_string()).unwrap_or(0),
+            self.fields.3 => guild,
+            self.fields.4 => self.rating_for(url, keywords, guild).index()
+        )
+    }
+
+    fn rating_for(&self, url: &str, keywords: &str, guild: u64) -> ContentRating {
+        self.ratings
+            .get(&(guild, url.to_string()))
+            .unwrap_or_else(|| ContentRating::from_tags(&split_keywords(keywords)))
+    }
+
+    /// Returns: `(url, keywords)`
+    fn read(
+        &self,
+        searcher: &Searcher,
+        doc_address: DocAddress
+    ) -> Result<Option<(String, String)>, Box<dyn core::error::Error + Send + Sync>> {
+        let doc = searcher.doc::<TantivyDocument>(doc_address)?;
+        let url = doc.get_first(self.fields.0).and_then(|value| value.as_value().as_str().map(str::to_owned));
+        let keywords = doc.get_first(self.fields.1).and_then(|value| value.as_value().as_str().map(str::to_owned));
+
+        Ok(url.zip(keywords))
+    }
+}
+
+/// Split indexed keywords back into tags.
+pub fn split_keywords(keywords: &str) -> Vec<String> {
+    keywords
+        .split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{ mock::MockDiscord, tests::TempDir };
+
+    fn nub(source: &str, tags: &[&str]) -> Nub {
+        Nub { source: source.into(), tags: tags.iter().map(|&tag| tag.into()).collect() }
+    }
+
+    /// `(source, tags)` of each nub, for comparing catalogs.
+    fn plain(nubs: &[Nub]) -> Vec<(String, Vec<String>)> {
+        nubs.iter()
+            .map(|nub| (nub.source.to_string(), nub.tags.iter().map(|tag| tag.to_string()).collect()))
+            .collect()
+    }
+
+    fn finder(dir: &TempDir, nubs: Vec<Nub>) -> NubFinder {
+        let finder = NubFinder::new(&dir.config()).unwrap();
+        finder.commit(nubs).unwrap();
+        finder
+    }
+
+    fn urls(results: Vec<(String, String)>) -> Vec<String> {
+        results.into_iter().map(|(url, _)| url).collect()
+    }
+
+    #[test]
+    fn catalog_round_trips() {
+        let dir = TempDir::new();
+        let path = dir.path().join("four.bin");
+        let nubs = vec![
+            nub("https://example.com/a.gif", &["sleepy", "bed"]),
+            nub("https://example.com/b.png", &["café", "猫", "🐱"]),
+            nub("https://example.com/c.webp", &[]),
+        ];
+
+        save_nubs(&path, &nubs).unwrap();
+        assert_eq!(plain(&load_nubs(&path).unwrap()), plain(&nubs));
+
+        save_nubs(&path, &Vec::new()).unwrap();
+        assert!(load_nubs(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupted_catalog_fails_to_load() {
+        let dir = TempDir::new();
+        let path = dir.path().join("four.bin");
+        save_nubs(&path, &vec![nub("https://example.com/a.gif", &["sleepy"])]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(load_nubs(&path).is_err());
+
+        std::fs::write(&path, vec![0xff; bytes.len()]).unwrap();
+        assert!(load_nubs(&path).is_err());
+
+        assert!(load_nubs(&dir.path().join("missing.bin")).is_err());
+    }
+
+    #[tokio::test]
+    async fn fetch_nubs_reads_the_endpoint() {
+        let server = MockDiscord::start().await.unwrap();
+        let nubs = vec![nub("https://example.com/a.gif", &["sleepy", "猫"])];
+        let endpoint = server.serve("memeslist", serde_json::to_vec(&nubs).unwrap());
+
+        assert_eq!(plain(&fetch_nubs(&endpoint).await.unwrap()), plain(&nubs));
+        assert!(fetch_nubs(&endpoint.replace("memeslist", "missing")).await.is_err());
+
+        let broken = server.serve("broken", b"<html>".to_vec());
+        assert!(fetch_nubs(&broken).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn get_nubs_fetches_a_missing_or_corrupted_catalog() {
+        let dir = TempDir::new();
+        let server = MockDiscord::start().await.unwrap();
+        let nubs = vec![nub("https://example.com/a.gif", &["sleepy"])];
+        let mut config = dir.config();
+        config.catalog.endpoint = server.serve("memeslist", serde_json::to_vec(&nubs).unwrap());
+
+        assert_eq!(plain(&get_nubs(&config).await.unwrap()), plain(&nubs));
+        assert_eq!(plain(&load_nubs(&config.catalog.path).unwrap()), plain(&nubs));
+
+        std::fs::write(&config.catalog.path, b"not a catalog").unwrap();
+        assert_eq!(plain(&get_nubs(&config).await.unwrap()), plain(&nubs));
+        assert_eq!(plain(&load_nubs(&config.catalog.path).unwrap()), plain(&nubs));
+
+        // the saved one is used without asking the endpoint
+        config.catalog.endpoint = server.serve("empty", b"[]".to_vec());
+        assert_eq!(plain(&get_nubs(&config).await.unwrap()), plain(&nubs));
+    }
+
+    #[test]
+    fn search_ranks_closer_matches_first() {
+        let dir = TempDir::new();
+        let finder = finder(&dir, vec![
+            nub("https://example.com/sleepy.gif", &["sleepy"]),
+            nub("https://example.com/sleepy-cat.gif", &["sleepy", "cat"]),
+            nub("https://example.com/dance.gif", &["dance", "cat"]),
+        ]);
+
+        let results = urls(finder.search("sleepy cat", None, ContentRating::Safe).unwrap());
+        assert_eq!(results[0], "https://example.com/sleepy-cat.gif");
+        assert_eq!(results.len(), 3);
+
+        assert_eq!(
+            finder.search("dance", None, ContentRating::Safe).unwrap(),
+            [("https://example.com/dance.gif".to_string(), "dance, cat".to_string())]
+        );
+        assert!(finder.search("xylophone", None, ContentRating::Nsfw).unwrap().is_empty());
+    }
+
+    #[test]
+    fn picks_make_a_nub_rank_higher() {
+        let dir = TempDir::new();
+        let finder = finder(&dir, vec![
+            nub("https://example.com/a.gif", &["sleepy"]),
+            nub("https://example.com/b.gif", &["sleepy"]),
+        ]);
+        let top = || urls(finder.search("sleepy", None, ContentRating::Safe).unwrap()).remove(0);
+
+        let other = if top() == "https://example.com/a.gif" { "https://example.com/b.gif" } else { "https://example.com/a.gif" };
+        finder.record_selection(other).unwrap();
+        finder.record_selection(other).unwrap();
+        assert_ne!(top(), other, "picks only count once flushed");
+        finder.flush_popularity().unwrap();
+        assert_eq!(top(), other);
+
+        // made-up picks are ignored
+        finder.record_selection("https://example.com/missing.gif").unwrap();
+    }
+
+    #[test]
+    fn search_is_scoped_to_the_guild_and_rating() {
+        let dir = TempDir::new();
+        let finder = finder(&dir, vec![
+            nub("https://example.com/safe.gif", &["cat"]),
+            nub("https://example.com/lewd.gif", &["cat", "nsfw"]),
+        ]);
+        finder.add_guild_nub(1, nub("https://example.com/guild.gif", &["cat"])).unwrap();
+        assert!(finder.add_guild_nub(2, nub("https://example.com/safe.gif", &["dog"])).is_err());
+
+        let mut results = urls(finder.search("cat", Some(1), ContentRating::Nsfw).unwrap());
+        results.sort();
+        assert_eq!(results, ["https://example.com/guild.gif", "https://example.com/lewd.gif", "https://example.com/safe.gif"]);
+
+        let results = urls(finder.search("cat", Some(2), ContentRating::Edgy).unwrap());
+        assert_eq!(results, ["https://example.com/safe.gif"]);
+
+        assert_eq!(finder.set_rating("https://example.com/safe.gif", ContentRating::Nsfw, Some(1), Editor::Guild(1)).unwrap(), Edited::Forbidden);
+        assert_eq!(finder.set_rating("https://example.com/guild.gif", ContentRating::Nsfw, Some(1), Editor::Guild(1)).unwrap(), Edited::Saved);
+        finder.set_rating("https://example.com/safe.gif", ContentRating::Nsfw, None, Editor::Owner).unwrap();
+        assert!(finder.search("cat", None, ContentRating::Edgy).unwrap().is_empty());
+    }
+
+    #[test]
+    fn guilds_keep_their_own_copy_of_a_url() {
+        let dir = TempDir::new();
+        let finder = finder(&dir, Vec::new());
+        let url = "https://example.com/cat.gif";
+        finder.add_guild_nub(1, nub(url, &["cat"])).unwrap();
+        assert!(finder.add_guild_nub(1, nub(url, &["dog"])).is_err());
+        finder.add_guild_nub(2, nub(url, &["kitten"])).unwrap();
+
+        let tags = vec!["cat".to_string(), "loaf".to_string()];
+        assert_eq!(finder.edit_tags(url, tags, Some(1), Editor::Guild(1)).unwrap(), Edited::Saved);
+        assert_eq!(finder.set_rating(url, ContentRating::Nsfw, Some(1), Editor::Guild(1)).unwrap(), Edited::Saved);
+        finder.commit(Vec::new()).unwrap();
+
+        assert_eq!(finder.get(url, Some(1)).unwrap().unwrap().1, "cat, loaf");
+        assert_eq!(finder.get(url, Some(2)).unwrap().unwrap().1, "kitten");
+        assert_eq!(finder.rating(url, Some(2)).unwrap(), Some(ContentRating::Safe));
+        // seen from nowhere in particular, it's as strict as any guild has it
+        assert_eq!(finder.rating(url, None).unwrap(), Some(ContentRating::Nsfw));
+        assert!(finder.get(url, None).unwrap().is_none());
+        assert_eq!(urls(finder.search("kitten", Some(2), ContentRating::Safe).unwrap()), [url]);
+    }
+
+    #[test]
+    fn old_edits_move_to_the_guild_of_their_nub() {
+        let dir = TempDir::new();
+        let config = dir.config();
+        let guild_nubs = Store::<u64, Vec<Nub>>::open(config.storage.path("guild_nubs.bin")).unwrap();
+        guild_nubs.update(1, |nubs| nubs.push(nub("https://example.com/ours.gif", &["cat"])));
+        guild_nubs.flush().unwrap();
+        let old = Store::<String, ContentRating>::open(config.storage.path("ratings.bin")).unwrap();
+        old.update("https://example.com/ours.gif".to_string(), |rating| *rating = ContentRating::Edgy);
+        old.update("https://example.com/a.gif".to_string(), |rating| *rating = ContentRating::Nsfw);
+        old.flush().unwrap();
+
+        let ratings = open_ratings(&config).unwrap();
+        assert_eq!(ratings.get(&(1, "https://example.com/ours.gif".to_string())), Some(ContentRating::Edgy));
+        assert_eq!(ratings.get(&(GLOBAL, "https://example.com/a.gif".to_string())), Some(ContentRating::Nsfw));
+        assert!(!config.storage.path("ratings.bin").exists());
+    }
+
+    #[test]
+    fn unicode_tags_are_searchable() {
+        let dir = TempDir::new();
+        let finder = finder(&dir, vec![
+            nub("https://example.com/cafe.gif", &["café"]),
+            nub("https://example.com/neko.gif", &["猫"]),
+            nub("https://example.com/emoji.gif", &["🐱", "Ünïcödé"]),
+        ]);
+        let search = |q: &str| urls(finder.search(q, None, ContentRating::Safe).unwrap());
+
+        assert_eq!(search("café"), ["https://example.com/cafe.gif"]);
+        assert_eq!(search("CAFÉ"), ["https://example.com/cafe.gif"]);
+        assert_eq!(search("猫"), ["https://example.com/neko.gif"]);
+        assert_eq!(search("ünïcödé"), ["https://example.com/emoji.gif"]);
+        assert_eq!(finder.get("https://example.com/emoji.gif", None).unwrap().unwrap().1, "🐱, Ünïcödé");
+    }
+
+    #[test]
+    fn tag_edits_survive_reloading_the_catalog() {
+        let dir = TempDir::new();
+        let catalog = || vec![nub("https://example.com/a.gif", &["sleepy", "bed"])];
+        let finder = finder(&dir, catalog());
+
+        let edit = |url: &str, tags: &[&str], editor| {
+            finder.edit_tags(url, tags.iter().map(|tag| tag.to_string()).collect(), None, editor).unwrap()
+        };
+        assert_eq!(edit("https://example.com/a.gif", &["sleepy", "awake"], Editor::Guild(1)), Edited::Forbidden);
+        assert_eq!(edit("https://example.com/a.gif", &["sleepy", "nap"], Editor::Owner), Edited::Saved);
+        assert_eq!(edit("https://example.com/missing.gif", &["nap"], Editor::Owner), Edited::Missing);
+
+        finder.commit(catalog()).unwrap();
+        assert_eq!(urls(finder.search("nap", None, ContentRating::Safe).unwrap()), ["https://example.com/a.gif"]);
+        assert!(finder.search("bed", None, ContentRating::Safe).unwrap().is_empty());
+
+        let overrides = finder.tag_overrides();
+        assert_eq!(overrides[0].0, (GLOBAL, "https://example.com/a.gif".to_string()));
+        assert_eq!(overrides[0].1.added, ["nap"]);
+        assert_eq!(overrides[0].1.removed, ["bed"]);
+    }
+
+    #[test]
+    fn split_keywords_drops_empty_tags() {
+        assert_eq!(split_keywords(" sleepy, ,bed ,, 猫 "), ["sleepy", "bed", "猫"]);
+        assert!(split_keywords(" , ").is_empty());
+    }
+
+    #[test]
+    fn any_query_searches_without_failing() {
+        let dir = TempDir::new();
+        let finder = finder(&dir, vec![
+            nub("https://example.com/a.gif", &["sleepy", "cat"]),
+            nub("https://example.com/b.gif", &["café", "猫", "nsfw"]),
+        ]);
+
+        // query syntax is where parsing could go wrong, so it's mixed into
+        // plain text as well as fully random strings
+        let query = prop_oneof![
+            any::<String>(),
+            "[a-zé猫 \"':()\\[\\]{}+\\-^~*!<>=,.\\\\/]{0,40}",
+        ];
+        proptest!(|(q in query, guild in proptest::option::of(any::<u64>()), rating in 0..3usize)| {
+            let results = finder.search(&q, guild, ContentRating::ALL[rating]);
+            prop_assert!(results.is_ok(), "{:?} failed: {:?}", q, results);
+            prop_assert!(results.unwrap().len() <= 10);
+            prop_assert!(finder.random(Some(&q), guild, ContentRating::ALL[rating]).is_ok());
+        });
     }
 }
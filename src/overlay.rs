@@ -0,0 +1,187 @@
+//! `/four-on`: the four-fingers hand put on an avatar or an uploaded image.
+//!
+//! Images are downloaded and drawn on within the same limits as
+//! `/nub-caption`'s.
+
+use std::{ error::Error, sync::Arc };
+
+use image::{ imageops::{ self, FilterType }, RgbaImage };
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandModel, CommandOption, CreateCommand, CreateOption, ResolvedUser };
+use twilight_model::{
+    application::interaction::{ application_command::CommandData, Interaction },
+    channel::Attachment,
+    http::{
+        attachment::Attachment as AttachmentFile,
+        interaction::{ InteractionResponse, InteractionResponseType },
+    },
+    user::User,
+};
+
+use crate::{
+    caption::{ drawing_error, edit_frames },
+    locale::Locale,
+    logging::Timed,
+    media::{ self, MAX_DOWNLOAD_SIZE },
+    response::{ ephemeral, respond },
+    AppState,
+};
+
+const FOUR_HAND_PNG: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/four_hand.png"));
+
+/// Hand height, in percent of the image's shorter side, when none is given.
+const DEFAULT_SCALE: i64 = 50;
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "four-on", desc = "put a four on someone's avatar or an image", contexts = "guild bot_dm private_channel")]
+pub struct FourOnCommand {
+    #[command(desc = "whose avatar, yours by default")]
+    user: Option<ResolvedUser>,
+
+    #[command(desc = "or an image to use instead")]
+    image: Option<Attachment>,
+
+    #[command(desc = "where the hand goes, bottom right by default")]
+    position: Option<OverlayPosition>,
+
+    #[command(desc = "how big the hand is, in percent of the image", min_value = 10, max_value = 100)]
+    scale: Option<i64>,
+}
+
+#[derive(CreateOption, CommandOption, Default)]
+pub enum OverlayPosition {
+    #[option(name = "top left", value = "top-left")]
+    TopLeft,
+
+    #[option(name = "top right", value = "top-right")]
+    TopRight,
+
+    #[option(name = "center", value = "center")]
+    Center,
+
+    #[option(name = "bottom left", value = "bottom-left")]
+    BottomLeft,
+
+    #[default]
+    #[option(name = "bottom right", value = "bottom-right")]
+    BottomRight,
+}
+
+impl OverlayPosition {
+    /// Where an `overlay`-sized image goes on a `base`-sized one.
+    const fn place(&self, base: (u32, u32), overlay: (u32, u32)) -> (i64, i64) {
+        let right = base.0.saturating_sub(overlay.0) as i64;
+        let bottom = base.1.saturating_sub(overlay.1) as i64;
+        match self {
+            Self::TopLeft => (0, 0),
+            Self::TopRight => (right, 0),
+            Self::Center => (right / 2, bottom / 2),
+            Self::BottomLeft => (0, bottom),
+            Self::BottomRight => (right, bottom),
+        }
+    }
+}
+
+pub async fn handle_command(
+    interaction: &Interaction,
+    cmd: CommandData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let four_on = match FourOnCommand::from_interaction(cmd.into()) {
+        Ok(o) => o,
+        Err(e) => {
+            tracing::error!(?e);
+            return Err(e.into());
+        }
+    };
+    let client = http.interaction(interaction.application_id);
+    let locale = Locale::of(interaction);
+
+    if four_on.image.as_ref().is_some_and(|image| image.size > MAX_DOWNLOAD_SIZE) {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("that's too big, images can be up to 25 MB"))
+        ).await;
+    }
+
+    // avatars can be GIFs, which take a while
+    client.create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::DeferredChannelMessageWithSource,
+            data: None,
+        })
+    ).timed().await?;
+
+    let url = match (four_on.image, four_on.user) {
+        (Some(image), _) => image.url,
+        (None, Some(user)) => avatar_url(&user.resolved),
+        (None, None) => match interaction.author() {
+            Some(user) => avatar_url(user),
+            None => {
+                client.update_response(&interaction.token).content(Some(locale.text("who am i putting it on?"))).timed().await?;
+                return Ok(());
+            }
+        },
+    };
+    let bytes = match media::download(&url).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!(url, ?e, "couldn't download image for four-on");
+            client.update_response(&interaction.token).content(Some(locale.text("i couldn't download that image :("))).timed().await?;
+            return Ok(());
+        }
+    };
+
+    let position = four_on.position.unwrap_or_default();
+    let scale = four_on.scale.unwrap_or(DEFAULT_SCALE);
+    state.stats.record_pick(format!("four-on:{}", position.value()), interaction.guild_id);
+
+    let rendered = tokio::task::spawn_blocking(move || {
+        let hand = image::load_from_memory(FOUR_HAND_PNG)?.into_rgba8();
+        let mut sized = None;
+        edit_frames(&bytes, "four", |image| {
+            // every frame of a GIF is the same size, so the hand is only resized once
+            let hand = sized.get_or_insert_with(|| resize(&hand, image, scale));
+            let (x, y) = position.place(image.dimensions(), hand.dimensions());
+            imageops::overlay(image, hand, x, y);
+        })
+    }).await?;
+
+    match rendered {
+        Ok((filename, rendered)) => {
+            client
+                .update_response(&interaction.token)
+                .attachments(&[AttachmentFile::from_bytes(filename, rendered, 1)])
+                .timed().await?;
+        }
+        Err(e) => {
+            tracing::warn!(url, ?e, "couldn't put a four on image");
+            client.update_response(&interaction.token).content(Some(locale.text(drawing_error(&*e)))).timed().await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The hand, `scale` percent as tall as `image`'s shorter side.
+fn resize(hand: &RgbaImage, image: &RgbaImage, scale: i64) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let target = (width.min(height) as i64 * scale / 100).max(1) as u32;
+    let target_width = (hand.width() * target / hand.height()).max(1);
+    imageops::resize(hand, target_width, target, FilterType::Triangle)
+}
+
+fn avatar_url(user: &User) -> String {
+    match user.avatar {
+        Some(hash) => {
+            let extension = if hash.is_animated() { "gif" } else { "png" };
+            format!("https://cdn.discordapp.com/avatars/{}/{}.{}?size=512", user.id, hash, extension)
+        }
+        None => format!("https://cdn.discordapp.com/embed/avatars/{}.png", (user.id.get() >> 22) % 6),
+    }
+}
@@ -0,0 +1,66 @@
+//! Content ratings for nubs and four variants, and what each place allows.
+
+use rkyv::Archive;
+use twilight_interactions::command::{ CommandOption, CreateOption };
+
+/// How edgy something is, mildest first.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    serde::Deserialize,
+    CreateOption,
+    CommandOption
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentRating {
+    #[default]
+    #[option(name = "safe for everyone", value = "safe")]
+    Safe,
+
+    #[option(name = "edgy", value = "edgy")]
+    Edgy,
+
+    #[option(name = "nsfw", value = "nsfw")]
+    Nsfw,
+}
+
+/// Tags that mark a catalog nub as NSFW when no moderator rated it.
+const NSFW_TAGS: &[&str] = &["nsfw", "lewd", "18+"];
+
+impl ContentRating {
+    pub const ALL: [Self; 3] = [Self::Safe, Self::Edgy, Self::Nsfw];
+
+    /// Guilds that haven't picked a rating get what the bot always sent.
+    pub const GUILD_DEFAULT: Self = Self::Edgy;
+
+    pub const fn index(self) -> u64 {
+        self as u64
+    }
+
+    /// The rating with this option value, like `edgy`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_lowercase();
+        Self::ALL.into_iter().find(|rating| rating.value() == value)
+    }
+
+    pub fn from_tags<S: AsRef<str>>(tags: &[S]) -> Self {
+        let nsfw = tags.iter().any(|tag| NSFW_TAGS.contains(&tag.as_ref().to_lowercase().as_str()));
+        if nsfw { Self::Nsfw } else { Self::Safe }
+    }
+
+    /// The most a place allows: the guild's own limit, and never NSFW outside
+    /// of age-restricted channels (DMs included).
+    pub fn allowed(guild: Option<Self>, in_guild: bool, nsfw_channel: bool) -> Self {
+        let channel = if in_guild && nsfw_channel { Self::Nsfw } else { Self::Edgy };
+        guild.unwrap_or(Self::GUILD_DEFAULT).min(channel)
+    }
+}
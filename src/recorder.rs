@@ -0,0 +1,301 @@
+//! Recording interactions so reported bugs can be replayed locally.
+//!
+//! With `RECORD_INTERACTIONS=<file>`, every interaction the bot receives and
+//! every response it sends is appended to the file as a line of JSON. The
+//! responses are caught by sending API requests through a local proxy that
+//! forwards them to Discord. Interaction tokens are written as
+//! `redacted-<interaction id>` and the bot token is never written, so
+//! recordings can be shared.
+//!
+//! `four replay <file>` runs the recorded interactions through the handlers
+//! against [`MockDiscord`] and prints what was sent this time, in the same
+//! format. Recordings in `src/tests/recordings/` are replayed by the tests
+//! and have to get the responses they recorded, which makes them regression
+//! tests as long as they don't depend on the real catalog.
+
+use std::{
+    error::Error,
+    fs::{ File, OpenOptions },
+    io::{ BufRead, BufWriter, Write },
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant },
+};
+
+use dashmap::DashMap;
+use reqwest::{ Client, Method };
+use tokio::{ io::AsyncWriteExt, net::{ TcpListener, TcpStream } };
+use twilight_gateway::Event;
+use twilight_model::{
+    application::interaction::Interaction,
+    gateway::payload::incoming::InteractionCreate,
+    id::{ marker::InteractionMarker, Id },
+};
+
+use crate::{ handle_event, mock::{ MockDiscord, Request }, server::{ read_request, MAX_API_BODY }, AppState };
+
+const DISCORD: &str = "https://discord.com";
+
+/// Interaction tokens stop working after this long, so they're forgotten.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// Headers that describe one connection rather than the request or
+/// response, and aren't passed along.
+const HOP_HEADERS: &[&str] = &["host", "connection", "content-length", "transfer-encoding", "keep-alive"];
+
+/// A line of a recording.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    /// An interaction as it was received.
+    Interaction { interaction: serde_json::Value },
+    /// A request answering one, as it was sent.
+    Response {
+        method: String,
+        /// Relative to the API root, like `interactions/1/redacted-1/callback`.
+        path: String,
+        status: u16,
+        body: serde_json::Value,
+        /// Names of the uploaded files.
+        files: Vec<String>,
+    },
+}
+
+impl Entry {
+    fn response(request: Request, status: u16) -> Self {
+        Self::Response {
+            method: request.method,
+            path: request.path,
+            status,
+            body: request.body,
+            files: request.files.into_iter().map(|(filename, _)| filename).collect(),
+        }
+    }
+}
+
+pub struct Recorder {
+    file: Mutex<BufWriter<File>>,
+    /// Where requests are forwarded to.
+    upstream: String,
+    client: Client,
+    /// Interaction token → the interaction's ID and when it was received.
+    tokens: DashMap<String, (Id<InteractionMarker>, Instant)>,
+}
+
+impl Recorder {
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Self::new(dotenvy::var("RECORD_INTERACTIONS")?.into(), DISCORD.to_string())
+    }
+
+    /// Append to the recording at `path`, forwarding requests to `upstream`.
+    pub fn new(path: PathBuf, upstream: String) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(file)),
+            upstream,
+            client: Client::new(),
+            tokens: DashMap::new(),
+        })
+    }
+
+    pub fn record_interaction(&self, interaction: &Interaction) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.tokens.retain(|_, (_, received)| received.elapsed() < TOKEN_LIFETIME);
+        self.tokens.insert(interaction.token.clone(), (interaction.id, Instant::now()));
+
+        let mut interaction_json = serde_json::to_value(interaction)?;
+        interaction_json["token"] = redacted(Some(interaction.id)).into();
+        self.write(&Entry::Interaction { interaction: interaction_json })
+    }
+
+    /// Start the proxy requests are recorded through.
+    ///
+    /// Returns: the address to give [`twilight_http::client::ClientBuilder::proxy`]
+    pub async fn proxy(self: Arc<Self>) -> std::io::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(self.clone().forward(stream));
+            }
+        });
+
+        Ok(addr)
+    }
+
+    /// Pass one request on and its response back, recording it if it
+    /// answers an interaction.
+    async fn forward(self: Arc<Self>, mut stream: TcpStream) {
+        let Ok((method, target, headers, body)) = read_request(&mut stream, MAX_API_BODY).await else {
+            return;
+        };
+
+        let mut request = self.client
+            .request(Method::from_bytes(method.as_bytes()).unwrap_or(Method::GET), format!("{}{}", self.upstream, target))
+            .body(body.clone());
+        for (name, value) in headers.iter().filter(|(name, _)| !HOP_HEADERS.contains(&name.as_str())) {
+            request = request.header(name, value);
+        }
+
+        let (status, response_headers, response_body) = match request.send().await {
+            Ok(response) => {
+                let status = response.status();
+                let response_headers = response.headers().clone();
+                (status, response_headers, response.bytes().await.unwrap_or_default().to_vec())
+            }
+            Err(e) => {
+                tracing::warn!(?e, target, "couldn't forward request to discord");
+                let _ = stream.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+                return;
+            }
+        };
+
+        let mut request = Request::new(method, &target, &headers, &body);
+        if request.path.starts_with("interactions/") || request.path.starts_with("webhooks/") {
+            request.path = self.redact_path(&request.path);
+            if let Err(e) = self.write(&Entry::response(request, status.as_u16())) {
+                tracing::warn!(error = e, "couldn't record response");
+            }
+        }
+
+        let mut head = format!("HTTP/1.1 {} {}\r\n", status.as_u16(), status.canonical_reason().unwrap_or_default());
+        for (name, value) in &response_headers {
+            if let Ok(value) = value.to_str()
+                && !HOP_HEADERS.contains(&name.as_str())
+            {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response_body.len()));
+
+        let _ = stream.write_all(head.as_bytes()).await;
+        let _ = stream.write_all(&response_body).await;
+        let _ = stream.shutdown().await;
+    }
+
+    /// Swap the token in `interactions/<id>/<token>/...` and
+    /// `webhooks/<application id>/<token>/...` for its redacted form.
+    fn redact_path(&self, path: &str) -> String {
+        let mut segments = path.split('/').map(str::to_string).collect::<Vec<_>>();
+        if let Some(token) = segments.get_mut(2) {
+            *token = redacted(self.tokens.get(token.as_str()).map(|entry| entry.0));
+        }
+        segments.join("/")
+    }
+
+    fn write(&self, entry: &Entry) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        serde_json::to_writer(&mut *file, entry)?;
+        writeln!(file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// What an interaction's token is recorded as.
+fn redacted(id: Option<Id<InteractionMarker>>) -> String {
+    match id {
+        Some(id) => format!("redacted-{}", id),
+        None => "redacted".to_string(),
+    }
+}
+
+/// Run the interactions in a recording through [`handle_event`] one at a
+/// time, and return them with what was sent in response this time.
+pub async fn replay(
+    recording: impl BufRead,
+    discord: &MockDiscord,
+    state: Arc<AppState>
+) -> Result<Vec<Entry>, Box<dyn Error + Send + Sync>> {
+    let http = Arc::new(discord.client());
+    let mut replayed = Vec::new();
+
+    for (i, line) in recording.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str::<Entry>(&line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        let Entry::Interaction { interaction: interaction_json } = entry else {
+            continue;
+        };
+
+        let interaction = serde_json::from_value::<Interaction>(interaction_json.clone())
+            .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        replayed.push(Entry::Interaction { interaction: interaction_json });
+
+        let event = Event::InteractionCreate(Box::new(InteractionCreate(interaction)));
+        if let Err(e) = handle_event(event, http.clone(), state.clone()).await {
+            tracing::warn!(line = i + 1, error = e, "interaction failed");
+        }
+        replayed.extend(discord.take().into_iter().map(|request| Entry::response(request, 200)));
+    }
+
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use twilight_http::Client as HttpClient;
+    use twilight_model::http::interaction::{ InteractionResponse, InteractionResponseType };
+    use twilight_util::builder::InteractionResponseDataBuilder;
+
+    use super::*;
+    use crate::tests::{ command, TempDir, APPLICATION_ID, INTERACTION_ID, TOKEN };
+
+    #[tokio::test]
+    async fn records_through_the_proxy_without_tokens() {
+        let dir = TempDir::new();
+        let path = dir.path().join("recording.jsonl");
+        let discord = MockDiscord::start().await.unwrap();
+
+        let recorder = Arc::new(Recorder::new(path.clone(), discord.url()).unwrap());
+        let http = HttpClient::builder()
+            .proxy(recorder.clone().proxy().await.unwrap().to_string(), true)
+            .ratelimiter(None)
+            .token("bot-token".to_string())
+            .build();
+
+        let interaction = serde_json::from_value::<Interaction>(command("hello", json!([]))).unwrap();
+        recorder.record_interaction(&interaction).unwrap();
+        http.interaction(interaction.application_id).create_response(
+            interaction.id,
+            &interaction.token,
+            &(InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(InteractionResponseDataBuilder::new().content("hi").build()),
+            })
+        ).await.unwrap();
+        // not an interaction response, so only forwarded
+        http.current_user_application().await.unwrap();
+
+        // Discord got the real thing
+        let forwarded = discord.take();
+        assert_eq!(forwarded.len(), 2);
+        assert_eq!(forwarded[0].path, format!("interactions/{}/{}/callback", INTERACTION_ID, TOKEN));
+        assert_eq!(forwarded[0].body["data"]["content"], "hi");
+
+        let recording = std::fs::read_to_string(&path).unwrap();
+        assert!(!recording.contains(TOKEN) && !recording.contains("bot-token"), "{}", recording);
+        let entries = recording
+            .lines()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+
+        let Entry::Interaction { interaction: recorded } = &entries[0] else {
+            panic!("expected the interaction first, got {:?}", entries[0]);
+        };
+        assert_eq!(recorded["token"], format!("redacted-{}", INTERACTION_ID));
+        assert_eq!(recorded["application_id"], APPLICATION_ID);
+        assert_eq!(entries[1], Entry::Response {
+            method: "POST".to_string(),
+            path: format!("interactions/{}/redacted-{}/callback", INTERACTION_ID, INTERACTION_ID),
+            status: 200,
+            body: json!({ "type": 4, "data": { "content": "hi" } }),
+            files: Vec::new(),
+        });
+    }
+}
@@ -0,0 +1,36 @@
+//! Shorthands for answering an interaction with a single response.
+
+use std::error::Error;
+
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    application::interaction::Interaction,
+    channel::message::MessageFlags,
+    http::interaction::{ InteractionResponse, InteractionResponseData, InteractionResponseType },
+};
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::logging::Timed;
+
+/// A message only whoever triggered the interaction sees.
+pub fn ephemeral(content: &str) -> InteractionResponseData {
+    InteractionResponseDataBuilder::new()
+        .content(content)
+        .flags(MessageFlags::EPHEMERAL)
+        .build()
+}
+
+pub async fn respond(
+    interaction: &Interaction,
+    http: &HttpClient,
+    kind: InteractionResponseType,
+    data: InteractionResponseData
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    http.interaction(interaction.application_id).create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse { kind, data: Some(data) })
+    ).timed().await?;
+
+    Ok(())
+}
@@ -0,0 +1,260 @@
+//! A small HTTP server for `/metrics`, `/healthz` and `/readyz`, on when
+//! `server.addr` is set.
+//!
+//! It only has to answer scrapers and probes, so it reads one request per
+//! connection and closes it after answering. Requests that are too big or
+//! too slow to arrive are turned away, since the port may be reachable by
+//! anyone.
+
+use std::{ collections::HashMap, net::SocketAddr, sync::Arc, time::Duration };
+
+use tokio::{ io::{ AsyncReadExt, AsyncWriteExt }, net::{ TcpListener, TcpStream } };
+
+use crate::{ health::HEALTH, metrics::METRICS, AppState };
+
+/// Longest request line and headers read, in bytes.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Biggest body read by [`serve`], which never needs one.
+const MAX_BODY: usize = 64 * 1024;
+
+/// Biggest body read from the bot itself, by the recorder's proxy and the
+/// mock, enough for any upload Discord takes.
+pub const MAX_API_BODY: usize = 500 * 1024 * 1024;
+
+/// How long a client gets to send its whole request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a request couldn't be read.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadError {
+    /// The connection closed first or sent something other than HTTP, so
+    /// there's nothing to answer.
+    Closed,
+    TooLarge,
+    TimedOut,
+}
+
+/// Start answering on `addr`.
+pub async fn serve(addr: SocketAddr, state: Arc<AppState>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(%addr, "serving metrics and health checks");
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream, state.clone()));
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle(mut stream: TcpStream, state: Arc<AppState>) {
+    let (method, target) = match read_request(&mut stream, MAX_BODY).await {
+        Ok((method, target, _, _)) => (method, target),
+        Err(ReadError::Closed) => return,
+        Err(ReadError::TooLarge) => {
+            write_response(&mut stream, "413 Payload Too Large", "text/plain", b"too large").await;
+            return;
+        }
+        Err(ReadError::TimedOut) => {
+            write_response(&mut stream, "408 Request Timeout", "text/plain", b"timed out").await;
+            return;
+        }
+    };
+    let path = target.split('?').next().unwrap_or_default();
+
+    let (status, content_type, body) = match (method.as_str(), path) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", METRICS.render().into_bytes()),
+        ("GET", "/healthz" | "/readyz") => {
+            let index_documents = state.nub_finder.num_docs().unwrap_or_else(|e| {
+                tracing::warn!(error = e, "couldn't count indexed nubs");
+                0
+            });
+            let report = HEALTH.report(index_documents, METRICS.catalog_age());
+            let ok = if path == "/healthz" { report.healthy } else { report.ready };
+            let status = if ok { "200 OK" } else { "503 Service Unavailable" };
+            (status, "application/json", serde_json::to_vec(&report).unwrap_or_default())
+        }
+        ("GET", _) => ("404 Not Found", "text/plain", b"not found".to_vec()),
+        _ => ("405 Method Not Allowed", "text/plain", b"method not allowed".to_vec()),
+    };
+
+    write_response(&mut stream, status, content_type, &body).await;
+}
+
+/// Read a request from a connection, with a body of up to `max_body` bytes,
+/// within [`READ_TIMEOUT`].
+///
+/// Returns: `(method, target, lowercased headers, body)`
+pub async fn read_request(
+    stream: &mut TcpStream,
+    max_body: usize
+) -> Result<(String, String, HashMap<String, String>, Vec<u8>), ReadError> {
+    tokio::time::timeout(READ_TIMEOUT, read(stream, max_body)).await.unwrap_or(Err(ReadError::TimedOut))
+}
+
+async fn read(
+    stream: &mut TcpStream,
+    max_body: usize
+) -> Result<(String, String, HashMap<String, String>, Vec<u8>), ReadError> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 8192];
+
+    let head_end = loop {
+        if let Some(i) = find(&buffer, b"\r\n\r\n") {
+            break i;
+        }
+        if buffer.len() > MAX_HEAD {
+            return Err(ReadError::TooLarge);
+        }
+        let read = stream.read(&mut chunk).await.ok().filter(|&read| read > 0).ok_or(ReadError::Closed)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    if head_end > MAX_HEAD {
+        return Err(ReadError::TooLarge);
+    }
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(ReadError::Closed);
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect::<HashMap<_, _>>();
+
+    let length = headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+    if length > max_body {
+        return Err(ReadError::TooLarge);
+    }
+    let mut body = buffer.split_off(head_end + 4);
+    while body.len() < length {
+        let read = stream.read(&mut chunk).await.ok().filter(|&read| read > 0).ok_or(ReadError::Closed)?;
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(length);
+
+    Ok((method, target, headers, body))
+}
+
+/// Answer and close the connection. Errors mean the client went away, so
+/// they're ignored.
+pub async fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(body).await;
+    let _ = stream.shutdown().await;
+}
+
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_cache_inmemory::DefaultInMemoryCache;
+
+    use super::*;
+    use crate::{ tests::{ Harness, TempDir }, variants::Variants };
+
+    /// Send one request to [`handle`] and return the status and body.
+    async fn request(method: &str, path: &str) -> (u16, String) {
+        let harness = Harness::new().await;
+        request_to(harness.state.clone(), method, path).await
+    }
+
+    async fn request_to(state: Arc<AppState>, method: &str, path: &str) -> (u16, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}{}", listener.local_addr().unwrap(), path);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle(stream, state).await;
+        });
+
+        let response = reqwest::Client::new()
+            .request(reqwest::Method::from_bytes(method.as_bytes()).unwrap(), url)
+            .send()
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn serves_metrics() {
+        METRICS.gateway_reconnects.inc();
+        let (status, body) = request("GET", "/metrics").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("four_gateway_reconnects_total"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_requests() {
+        assert_eq!(request("GET", "/nope").await.0, 404);
+        assert_eq!(request("POST", "/metrics").await.0, 405);
+    }
+
+    /// Send raw bytes to [`handle`] and return the status line.
+    async fn raw_request(bytes: Vec<u8>) -> String {
+        let harness = Harness::new().await;
+        let state = harness.state.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle(stream, state).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // the server may answer and close before everything is written
+        let _ = stream.write_all(&bytes).await;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        String::from_utf8_lossy(&response).lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_requests() {
+        let headers = format!("GET /metrics HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_HEAD));
+        assert_eq!(raw_request(headers.into_bytes()).await, "HTTP/1.1 413 Payload Too Large");
+
+        let body = format!("POST /metrics HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1);
+        assert_eq!(raw_request(body.into_bytes()).await, "HTTP/1.1 413 Payload Too Large");
+
+        let small = b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec();
+        assert_eq!(raw_request(small).await, "HTTP/1.1 200 OK");
+    }
+
+    #[tokio::test]
+    async fn reports_health() {
+        // no gateway in tests, so it's healthy while starting but never ready
+        let (status, body) = request("GET", "/healthz").await;
+        assert_eq!(status, 200, "{}", body);
+        let report = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(report["shard"], "starting");
+        assert_eq!(report["index_documents"], crate::tests::CATALOG.len());
+
+        let (status, body) = request("GET", "/readyz").await;
+        assert_eq!(status, 503, "{}", body);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["ready"], false);
+    }
+
+    #[tokio::test]
+    async fn answers_before_the_catalog_is_indexed() {
+        let dir = TempDir::new();
+        let variants = Variants::load().unwrap();
+        let state = AppState::new(dir.config(), DefaultInMemoryCache::new(), None, None, variants).unwrap();
+
+        let (status, body) = request_to(Arc::new(state), "GET", "/readyz").await;
+        assert_eq!(status, 503, "{}", body);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["index_documents"], 0);
+    }
+}
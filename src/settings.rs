@@ -0,0 +1,296 @@
+//! Per-guild settings and the `/four-config` command that edits them.
+
+use std::{ error::Error, sync::Arc };
+
+use rkyv::Archive;
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::{
+    application::interaction::{ application_command::CommandData, Interaction },
+    guild::Permissions,
+    http::interaction::InteractionResponseType,
+    id::{ marker::ChannelMarker, Id },
+};
+
+use crate::{
+    locale::Locale,
+    rating::ContentRating,
+    response::{ ephemeral, respond },
+    store::Store,
+    AppState,
+};
+
+pub type SettingsStore = Store<u64, GuildSettings>;
+
+#[derive(Debug, Clone, Default, Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct GuildSettings {
+    /// Commands nobody in the guild may run.
+    pub disabled_commands: Vec<String>,
+    /// Value of the [`crate::variants::Variant`] `/four` sends when none is
+    /// picked. Unset picks one at random.
+    pub default_variant: Option<String>,
+    /// Channels the bot may post in. Empty means any channel.
+    pub channels: Vec<u64>,
+    /// The edgiest content the bot may send. Unset is
+    /// [`ContentRating::GUILD_DEFAULT`].
+    pub content_rating: Option<ContentRating>,
+    /// Whether plain messages get answered, see [`crate::auto_respond`].
+    /// Unset follows `AUTO_RESPOND_GUILDS`.
+    pub auto_respond: Option<bool>,
+}
+
+impl GuildSettings {
+    pub fn allows_command(&self, name: &str) -> bool {
+        !self.disabled_commands.iter().any(|disabled| disabled == name)
+    }
+
+    pub fn allows_channel(&self, channel_id: Option<Id<ChannelMarker>>) -> bool {
+        self.channels.is_empty() ||
+            channel_id.is_some_and(|id| self.channels.contains(&id.get()))
+    }
+}
+
+fn manage_guild() -> Permissions {
+    Permissions::MANAGE_GUILD
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(
+    name = "four-config",
+    desc = "configure four for this server",
+    default_permissions = "manage_guild",
+    contexts = "guild"
+)]
+pub enum FourConfigCommand {
+    #[command(name = "show")]
+    Show(ConfigShowCommand),
+
+    #[command(name = "disable")]
+    Disable(ConfigDisableCommand),
+
+    #[command(name = "enable")]
+    Enable(ConfigEnableCommand),
+
+    #[command(name = "default-variant")]
+    DefaultVariant(ConfigDefaultVariantCommand),
+
+    #[command(name = "channel")]
+    Channel(ConfigChannelCommand),
+
+    #[command(name = "rating")]
+    Rating(ConfigRatingCommand),
+
+    #[command(name = "auto-respond")]
+    AutoRespond(ConfigAutoRespondCommand),
+
+    #[command(name = "reset")]
+    Reset(ConfigResetCommand),
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "show", desc = "show the current settings")]
+pub struct ConfigShowCommand;
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "disable", desc = "stop a command from being used here")]
+pub struct ConfigDisableCommand {
+    #[command(desc = "the command's name, like nub or rate-ragebait")]
+    command: String,
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "enable", desc = "let a disabled command be used again")]
+pub struct ConfigEnableCommand {
+    #[command(desc = "the command's name, like nub or rate-ragebait")]
+    command: String,
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "default-variant", desc = "pick what /four sends by default")]
+pub struct ConfigDefaultVariantCommand {
+    #[command(desc = "leave empty to go back to a random one")]
+    variant: Option<String>,
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "channel", desc = "allow or disallow posting in a channel")]
+pub struct ConfigChannelCommand {
+    #[command(desc = "once any channel is allowed, only allowed channels are used")]
+    channel: Id<ChannelMarker>,
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "rating", desc = "pick how edgy things can get here")]
+pub struct ConfigRatingCommand {
+    #[command(desc = "nsfw only goes to age-restricted channels either way")]
+    rating: ContentRating,
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "auto-respond", desc = "answer messages that mention four or nubs")]
+pub struct ConfigAutoRespondCommand {
+    #[command(desc = "whether to answer them")]
+    enabled: bool,
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "reset", desc = "go back to the default settings")]
+pub struct ConfigResetCommand;
+
+pub async fn handle_command(
+    interaction: &Interaction,
+    cmd: CommandData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let config = match FourConfigCommand::from_interaction(cmd.into()) {
+        Ok(o) => o,
+        Err(e) => {
+            tracing::error!(?e);
+            return Err(e.into());
+        }
+    };
+
+    let locale = Locale::of(interaction);
+    let can_manage = interaction.member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.contains(Permissions::MANAGE_GUILD));
+
+    let content = match interaction.guild_id {
+        Some(guild_id) if can_manage => {
+            let guild_id = guild_id.get();
+            let content = match config {
+                FourConfigCommand::Show(_) => {
+                    return respond(
+                        interaction,
+                        http,
+                        InteractionResponseType::ChannelMessageWithSource,
+                        ephemeral(&describe(&state.settings.get(&guild_id).unwrap_or_default(), locale))
+                    ).await;
+                }
+
+                FourConfigCommand::Disable(ConfigDisableCommand { command }) => {
+                    let command = command.trim_start_matches('/').to_string();
+                    if command == "four-config" {
+                        locale.text("nice try").to_string()
+                    } else if !crate::commands(&state.variants).iter().any(|known| known.name == command) {
+                        locale.format("there's no command called `{}`", &[&command])
+                    } else {
+                        state.settings.update(guild_id, |settings| {
+                            if settings.allows_command(&command) {
+                                settings.disabled_commands.push(command.clone());
+                            }
+                        });
+                        locale.format("`{}` is now disabled", &[&command])
+                    }
+                }
+
+                FourConfigCommand::Enable(ConfigEnableCommand { command }) => {
+                    let command = command.trim_start_matches('/').to_string();
+                    state.settings.update(guild_id, |settings| {
+                        settings.disabled_commands.retain(|disabled| *disabled != command);
+                    });
+                    locale.format("`{}` is now enabled", &[&command])
+                }
+
+                FourConfigCommand::DefaultVariant(ConfigDefaultVariantCommand { variant }) => {
+                    match variant {
+                        Some(value) if state.variants.get(&value).is_none() => {
+                            locale.format("there's no variant called `{}`", &[&value])
+                        }
+                        value => {
+                            let content = match &value {
+                                Some(value) => locale.format("`/four` now defaults to `{}`", &[value]),
+                                None => locale.text("`/four` is back to a random default").to_string(),
+                            };
+                            state.settings.update(guild_id, |settings| {
+                                settings.default_variant = value;
+                            });
+                            content
+                        }
+                    }
+                }
+
+                FourConfigCommand::Channel(ConfigChannelCommand { channel }) => {
+                    state.settings.update(guild_id, |settings| {
+                        if let Some(i) = settings.channels.iter().position(|id| *id == channel.get()) {
+                            settings.channels.remove(i);
+                            locale.format("<#{}> is no longer allowed", &[&channel])
+                        } else {
+                            settings.channels.push(channel.get());
+                            locale.format("<#{}> is now allowed", &[&channel])
+                        }
+                    })
+                }
+
+                FourConfigCommand::Rating(ConfigRatingCommand { rating }) => {
+                    state.settings.update(guild_id, |settings| {
+                        settings.content_rating = Some(rating);
+                    });
+                    locale.format("content is now limited to `{}`", &[&rating.value()])
+                }
+
+                FourConfigCommand::AutoRespond(ConfigAutoRespondCommand { enabled }) => {
+                    state.settings.update(guild_id, |settings| {
+                        settings.auto_respond = Some(enabled);
+                    });
+                    match (enabled, &state.auto_responder) {
+                        (true, Some(_)) => locale.text("i'll answer messages that mention four or nubs").to_string(),
+                        (true, None) => locale.text("saved, but auto-responding isn't turned on for this bot").to_string(),
+                        (false, _) => locale.text("i won't answer messages on my own").to_string(),
+                    }
+                }
+
+                FourConfigCommand::Reset(_) => {
+                    state.settings.remove(&guild_id);
+                    locale.text("settings are back to the defaults").to_string()
+                }
+            };
+
+            tokio::task::spawn_blocking(move || state.settings.flush()).await??;
+            content
+        }
+        Some(_) => locale.text("you need the Manage Server permission for this").to_string(),
+        None => locale.text("this only works in servers").to_string(),
+    };
+
+    respond(interaction, http, InteractionResponseType::ChannelMessageWithSource, ephemeral(&content)).await
+}
+
+/// Summarize the settings for `/four-config show`.
+fn describe(settings: &GuildSettings, locale: Locale) -> String {
+    let disabled = if settings.disabled_commands.is_empty() {
+        locale.text("none").to_string()
+    } else {
+        settings.disabled_commands
+            .iter()
+            .map(|name| format!("`/{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let channels = if settings.channels.is_empty() {
+        locale.text("any").to_string()
+    } else {
+        settings.channels
+            .iter()
+            .map(|id| format!("<#{}>", id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    locale.format(
+        "**disabled commands:** {}\n**default variant:** {}\n**channels:** {}\n**rating:** {}\n**auto-respond:** {}",
+        &[
+            &disabled,
+            &settings.default_variant.as_deref().unwrap_or(locale.text("random")),
+            &channels,
+            &settings.content_rating.unwrap_or(ContentRating::GUILD_DEFAULT).value(),
+            &match settings.auto_respond {
+                Some(true) => locale.text("on"),
+                Some(false) => locale.text("off"),
+                None => locale.text("default"),
+            },
+        ]
+    )
+}
@@ -0,0 +1,238 @@
+//! Usage analytics, rolled up by guild and hour, and the `/four-stats`
+//! command.
+//!
+//! Each guild only ever sees its own counts, and everything used outside of
+//! servers is counted together. Only the catalog's owner sees which servers
+//! are busiest.
+
+use std::{ cmp::Reverse, error::Error, path::PathBuf, sync::Arc, time::{ SystemTime, UNIX_EPOCH } };
+
+use rkyv::Archive;
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandModel, CommandOption, CreateCommand, CreateOption };
+use twilight_model::{
+    application::interaction::{ application_command::CommandData, Interaction },
+    channel::message::MessageFlags,
+    http::interaction::{ InteractionResponse, InteractionResponseType },
+    id::{ marker::GuildMarker, Id },
+};
+use twilight_util::builder::{ embed::{ EmbedBuilder, EmbedFieldBuilder }, InteractionResponseDataBuilder };
+
+use crate::{ locale::Locale, logging::Timed, store::Store, AppState };
+
+/// How many entries each top list shows.
+const TOP: usize = 5;
+
+/// Where counts from outside of servers go.
+const NO_GUILD: u64 = 0;
+
+/// Counts for one guild and hour, keyed by the guild ID and hours since the
+/// epoch.
+#[derive(Debug, Clone, Default, Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Rollup {
+    /// Command name → uses.
+    pub commands: Vec<(String, u64)>,
+    /// What was sent, like `four:silly-nub` or `nub:<url>` → times.
+    pub picks: Vec<(String, u64)>,
+    /// Button or menu, like `reroll` → clicks.
+    pub components: Vec<(String, u64)>,
+}
+
+pub struct Stats {
+    store: Store<(u64, u64), Rollup>,
+}
+
+impl Stats {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Self { store: Store::open(path)? })
+    }
+
+    /// Count a dispatched command.
+    pub fn record_command(&self, name: &str, guild_id: Option<Id<GuildMarker>>) {
+        self.store.update(key(guild_id), |rollup| bump(&mut rollup.commands, name.to_string()));
+    }
+
+    /// Count a click on a button or menu, by the action in its custom ID.
+    pub fn record_component(&self, action: &str, guild_id: Option<Id<GuildMarker>>) {
+        self.store.update(key(guild_id), |rollup| bump(&mut rollup.components, action.to_string()));
+    }
+
+    /// Count what a command ended up sending.
+    pub fn record_pick(&self, source: String, guild_id: Option<Id<GuildMarker>>) {
+        self.store.update(key(guild_id), |rollup| bump(&mut rollup.picks, source));
+    }
+
+    /// Add up a guild's hours since `since` (in hours since the epoch).
+    pub fn rollup(&self, guild_id: Option<Id<GuildMarker>>, since: u64) -> Rollup {
+        let guild = key(guild_id).0;
+        let mut total = Rollup::default();
+        for ((rollup_guild, hour), rollup) in self.store.entries() {
+            if rollup_guild != guild || hour < since {
+                continue;
+            }
+            for (name, count) in rollup.commands {
+                add(&mut total.commands, name, count);
+            }
+            for (source, count) in rollup.picks {
+                add(&mut total.picks, source, count);
+            }
+            for (action, count) in rollup.components {
+                add(&mut total.components, action, count);
+            }
+        }
+        total
+    }
+
+    /// Add up every guild's commands since `since` (in hours since the epoch),
+    /// leaving out everything used outside of servers.
+    pub fn busiest(&self, since: u64) -> Vec<(u64, u64)> {
+        let mut guilds = Vec::new();
+        for ((guild, hour), rollup) in self.store.entries() {
+            if guild == NO_GUILD || hour < since {
+                continue;
+            }
+            add(&mut guilds, guild, rollup.commands.iter().map(|(_, count)| count).sum());
+        }
+        guilds
+    }
+
+    pub fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.store.flush()
+    }
+}
+
+/// Where counts from a guild, this hour, go.
+fn key(guild_id: Option<Id<GuildMarker>>) -> (u64, u64) {
+    (guild_id.map_or(NO_GUILD, |id| id.get()), current_hour())
+}
+
+fn current_hour() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 3600
+}
+
+fn bump<K: PartialEq>(counts: &mut Vec<(K, u64)>, key: K) {
+    add(counts, key, 1);
+}
+
+fn add<K: PartialEq>(counts: &mut Vec<(K, u64)>, key: K, n: u64) {
+    match counts.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, count)) => {
+            *count += n;
+        }
+        None => counts.push((key, n)),
+    }
+}
+
+/// The `TOP` largest counts, largest first.
+fn top<K>(mut counts: Vec<(K, u64)>) -> Vec<(K, u64)> {
+    counts.sort_by_key(|(_, count)| Reverse(*count));
+    counts.truncate(TOP);
+    counts
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "four-stats", desc = "see what's popular", contexts = "guild bot_dm private_channel")]
+pub struct FourStatsCommand {
+    #[command(desc = "how far back to look, all time by default")]
+    window: Option<StatsWindow>,
+}
+
+#[derive(CreateOption, CommandOption, Default)]
+pub enum StatsWindow {
+    #[option(name = "last 24 hours", value = "day")]
+    Day,
+
+    #[option(name = "last 7 days", value = "week")]
+    Week,
+
+    #[option(name = "last 30 days", value = "month")]
+    Month,
+
+    #[default]
+    #[option(name = "all time", value = "all")]
+    All,
+}
+
+impl StatsWindow {
+    const fn hours(&self) -> Option<u64> {
+        match self {
+            Self::Day => Some(24),
+            Self::Week => Some(24 * 7),
+            Self::Month => Some(24 * 30),
+            Self::All => None,
+        }
+    }
+}
+
+pub async fn handle_command(
+    interaction: &Interaction,
+    cmd: CommandData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let stats = match FourStatsCommand::from_interaction(cmd.into()) {
+        Ok(o) => o,
+        Err(e) => {
+            tracing::error!(?e);
+            return Err(e.into());
+        }
+    };
+    let window = stats.window.unwrap_or_default();
+    let since = window.hours().map_or(0, |hours| current_hour().saturating_sub(hours));
+    let rollup = state.stats.rollup(interaction.guild_id, since);
+    let locale = Locale::of(interaction);
+
+    let commands = list(top(rollup.commands), locale, |name| format!("`/{}`", name));
+    let picks = list(top(rollup.picks), locale, |source| {
+        match source.split_once(':') {
+            Some(("nub", url)) => format!("[nub]({})", url),
+            Some((command, value)) => format!("`/{} {}`", command, value),
+            None => source.clone(),
+        }
+    });
+    let components = list(top(rollup.components), locale, |action| format!("`{}`", action));
+
+    let mut embed = EmbedBuilder::new()
+        .title(locale.format("four stats ({})", &[&window.value()]))
+        .field(EmbedFieldBuilder::new(locale.text("top commands"), commands).inline())
+        .field(EmbedFieldBuilder::new(locale.text("top picks"), picks).inline())
+        .field(EmbedFieldBuilder::new(locale.text("top buttons"), components).inline());
+    let mut data = InteractionResponseDataBuilder::new();
+
+    let owner = state.config.catalog.owner;
+    if owner.is_some() && interaction.author_id().map(|id| id.get()) == owner {
+        let guilds = list(top(state.stats.busiest(since)), locale, |id| {
+            Id::<GuildMarker>::new_checked(*id)
+                .and_then(|id| state.cache.guild(id))
+                .map_or_else(|| id.to_string(), |guild| guild.name().to_string())
+        });
+        embed = embed.field(EmbedFieldBuilder::new(locale.text("busiest servers"), guilds).inline());
+        // other servers are nobody else's business
+        if interaction.guild_id.is_some() {
+            data = data.flags(MessageFlags::EPHEMERAL);
+        }
+    }
+
+    http.interaction(interaction.application_id).create_response(
+        interaction.id,
+        &interaction.token,
+        &(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(data.embeds([embed.build()]).build()),
+        })
+    ).timed().await?;
+
+    Ok(())
+}
+
+fn list<K>(counts: Vec<(K, u64)>, locale: Locale, label: impl Fn(&K) -> String) -> String {
+    if counts.is_empty() {
+        return locale.text("nothing yet").to_string();
+    }
+    counts
+        .iter()
+        .enumerate()
+        .map(|(i, (key, count))| format!("{}. {} ({})", i + 1, label(key), count))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
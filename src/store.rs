@@ -0,0 +1,81 @@
+//! A small key-value store kept in memory and snapshotted to disk with rkyv,
+//! the same way the nub catalog is kept in `four.bin`.
+
+use std::{ hash::Hash, path::PathBuf, sync::Mutex };
+
+use dashmap::DashMap;
+use rkyv::{
+    api::high::{ HighSerializer, HighValidator },
+    bytecheck::CheckBytes,
+    de::Pool,
+    rancor::{ self, Strategy },
+    ser::allocator::ArenaHandle,
+    util::AlignedVec,
+    Archive,
+    Deserialize,
+    Serialize,
+};
+
+pub struct Store<K, V> {
+    path: PathBuf,
+    map: DashMap<K, V>,
+    /// Held while writing so snapshots don't interleave.
+    write_lock: Mutex<()>,
+}
+
+impl<K, V> Store<K, V>
+    where
+        K: Eq + Hash + Clone,
+        V: Clone,
+        Vec<(K, V)>: Archive + for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>,
+        <Vec<(K, V)> as Archive>::Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>> +
+            Deserialize<Vec<(K, V)>, Strategy<Pool, rancor::Error>>
+{
+    /// Load the store from `path`, starting empty if the file doesn't exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Box<dyn core::error::Error + Send + Sync>> {
+        let path = path.into();
+        let map = if std::fs::exists(&path)? {
+            rkyv::from_bytes::<Vec<(K, V)>, rancor::Error>(&std::fs::read(&path)?)?
+                .into_iter()
+                .collect()
+        } else {
+            DashMap::new()
+        };
+
+        Ok(Self { path, map, write_lock: Mutex::new(()) })
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key).map(|value| value.clone())
+    }
+
+    /// Change the value for `key` in memory, starting from its default.
+    ///
+    /// Call [`Store::flush`] to write it out.
+    pub fn update<R>(&self, key: K, f: impl FnOnce(&mut V) -> R) -> R where V: Default {
+        f(&mut self.map.entry(key).or_default())
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.map.remove(key).map(|(_, value)| value)
+    }
+
+    /// A copy of every entry.
+    pub fn entries(&self) -> Vec<(K, V)> {
+        self.map
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Write a snapshot, replacing the file atomically.
+    pub fn flush(&self) -> Result<(), Box<dyn core::error::Error + Send + Sync>> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let bytes = rkyv::to_bytes::<rancor::Error>(&self.entries())?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+//! Nubs submitted by guild members with `/nub-add`.
+//!
+//! A submission goes through a modal for its tags, then waits in the channel
+//! with approve/reject buttons until a moderator picks one. Approved nubs are
+//! only searchable from the guild they were added to.
+//!
+//! Links to uploads stop working after a while, so uploads are only taken
+//! with the media cache on and are copied into it right away, to be sent from
+//! there once the link expires.
+
+use std::{ error::Error, sync::Arc };
+
+use rkyv::Archive;
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::{
+    application::interaction::{
+        application_command::CommandData,
+        message_component::MessageComponentInteractionData,
+        modal::ModalInteractionData,
+        Interaction,
+    },
+    channel::{
+        message::{
+            component::{ ActionRow, Button, ButtonStyle, TextInput, TextInputStyle },
+            AllowedMentions,
+            Component,
+        },
+        Attachment,
+    },
+    guild::Permissions,
+    http::interaction::InteractionResponseType,
+};
+use twilight_util::builder::{
+    embed::{ EmbedBuilder, EmbedFieldBuilder, ImageSource },
+    InteractionResponseDataBuilder,
+};
+
+use crate::{ locale::Locale, nub::Nub, response::{ ephemeral, respond }, store::Store, AppState };
+
+/// Pending submissions, by the ID of the interaction that submitted them.
+pub type SubmissionStore = Store<u64, Submission>;
+
+pub const SUBMIT_ID: &str = "nub-add";
+pub const APPROVE_ID: &str = "nub-approve";
+pub const REJECT_ID: &str = "nub-reject";
+
+/// Biggest upload kept in the media cache, in bytes.
+const MAX_UPLOAD_SIZE: u64 = 10 * 1024 * 1024;
+
+const URL_INPUT_ID: &str = "url";
+const TAGS_INPUT_ID: &str = "tags";
+
+#[derive(Debug, Clone, Default, Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Submission {
+    pub guild: u64,
+    pub user: u64,
+    pub url: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(name = "nub-add", desc = "suggest a nub for this server", contexts = "guild")]
+pub struct NubAddCommand {
+    #[command(desc = "a link to the image or gif")]
+    url: Option<String>,
+
+    #[command(desc = "or upload it")]
+    image: Option<Attachment>,
+}
+
+/// Ask for tags, carrying the URL along in a pre-filled input.
+pub async fn handle_command(
+    interaction: &Interaction,
+    cmd: CommandData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let add = match NubAddCommand::from_interaction(cmd.into()) {
+        Ok(o) => o,
+        Err(e) => {
+            tracing::error!(?e);
+            return Err(e.into());
+        }
+    };
+
+    let locale = Locale::of(interaction);
+
+    let upload = match (add.url, add.image) {
+        (Some(url), _) => Some(url),
+        (None, Some(image)) => {
+            let refusal = match &state.media {
+                None => Some("uploads aren't kept here, send a link instead"),
+                Some(_) if image.size > MAX_UPLOAD_SIZE => Some("that's too big, uploads can be up to 10 MB"),
+                Some(media) => {
+                    // the modal has to be the first response, so the copy is
+                    // made while it's being filled in
+                    let (media, url) = (media.clone(), image.url.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = media.fetch(&url).await {
+                            tracing::warn!(url, ?e, "couldn't cache uploaded nub");
+                        }
+                    });
+                    None
+                }
+            };
+            if let Some(refusal) = refusal {
+                return respond(
+                    interaction,
+                    http,
+                    InteractionResponseType::ChannelMessageWithSource,
+                    ephemeral(locale.text(refusal))
+                ).await;
+            }
+            Some(image.url)
+        }
+        (None, None) => None,
+    };
+
+    let data = match upload {
+        Some(url) =>
+            InteractionResponseDataBuilder::new()
+                .custom_id(SUBMIT_ID)
+                .title(locale.text("add a nub"))
+                .components([
+                    text_input(URL_INPUT_ID, locale.text("link"), TextInputStyle::Short, Some(url)),
+                    text_input(
+                        TAGS_INPUT_ID,
+                        locale.text("tags, separated by commas"),
+                        TextInputStyle::Paragraph,
+                        None
+                    ),
+                ])
+                .build(),
+        None => {
+            return respond(
+                interaction,
+                http,
+                InteractionResponseType::ChannelMessageWithSource,
+                ephemeral(locale.text("give me a link or upload an image"))
+            ).await;
+        }
+    };
+
+    respond(interaction, http, InteractionResponseType::Modal, data).await
+}
+
+/// Queue the submission and post it for moderators.
+pub async fn handle_modal(
+    interaction: &Interaction,
+    modal: ModalInteractionData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (Some(guild_id), Some(user_id)) = (interaction.guild_id, interaction.author_id()) else {
+        return Ok(());
+    };
+
+    let locale = Locale::of(interaction);
+    let mut url = None;
+    let mut tags = Vec::new();
+    for input in modal.components.into_iter().flat_map(|row| row.components) {
+        match input.custom_id.as_str() {
+            URL_INPUT_ID => {
+                url = input.value.map(|value| value.trim().to_string());
+            }
+            TAGS_INPUT_ID => {
+                tags = input.value
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect();
+            }
+            _ => (),
+        }
+    }
+
+    let Some(url) = url.filter(|url| ImageSource::url(url).is_ok()) else {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("that doesn't look like a link"))
+        ).await;
+    };
+    let exists = tokio::task::spawn_blocking({
+        let (finder, url) = (state.clone(), url.clone());
+        move || finder.nub_finder.get(&url, Some(guild_id.get()))
+    }).await??;
+    if exists.is_some() {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("that's already a nub"))
+        ).await;
+    }
+    if tags.is_empty() {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("a nub needs at least one tag to be found"))
+        ).await;
+    }
+
+    let id = interaction.id.get();
+    let submission = Submission { guild: guild_id.get(), user: user_id.get(), url, tags };
+    state.submissions.update(id, |pending| {
+        *pending = submission.clone();
+    });
+    let store = state.clone();
+    tokio::task::spawn_blocking(move || store.submissions.flush()).await??;
+
+    // the post is for the whole server, not just whoever suggested it
+    let locale = Locale::of_guild(interaction);
+    let data = InteractionResponseDataBuilder::new()
+        .content(locale.format("<@{}> suggested a nub, a moderator can approve it", &[&user_id]))
+        .allowed_mentions(AllowedMentions::default())
+        .embeds([
+            EmbedBuilder::new()
+                .image(ImageSource::url(&submission.url)?)
+                .field(EmbedFieldBuilder::new(locale.text("tags"), submission.tags.join(", ")))
+                .build(),
+        ])
+        .components([
+            Component::ActionRow(ActionRow {
+                components: vec![
+                    button(format!("{}:{}", APPROVE_ID, id), locale.text("approve"), ButtonStyle::Success),
+                    button(format!("{}:{}", REJECT_ID, id), locale.text("reject"), ButtonStyle::Danger),
+                ],
+            }),
+        ])
+        .build();
+
+    respond(interaction, http, InteractionResponseType::ChannelMessageWithSource, data).await
+}
+
+/// Approve or reject a pending submission.
+pub async fn handle_component(
+    interaction: &Interaction,
+    component: MessageComponentInteractionData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some((action, id)) = component.custom_id.split_once(':') else {
+        return Ok(());
+    };
+    let Ok(id) = id.parse::<u64>() else {
+        return Ok(());
+    };
+
+    let locale = Locale::of(interaction);
+    let is_moderator = interaction.member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.contains(Permissions::MANAGE_MESSAGES));
+    if !is_moderator {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("only moderators can review nubs"))
+        ).await;
+    }
+
+    // taken out so nobody else reviews it meanwhile, and put back if it
+    // can't be added
+    let Some(submission) = state.submissions.remove(&id) else {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("someone already took care of this one"))
+        ).await;
+    };
+    let locale = Locale::of_guild(interaction);
+    let moderator = interaction.author_id().map_or_else(String::new, |id| locale.format(" by <@{}>", &[&id]));
+
+    let content = match action {
+        APPROVE_ID => {
+            let finder = state.clone();
+            let nub = Nub {
+                source: submission.url.clone().into(),
+                tags: submission.tags.iter().map(|tag| tag.as_str().into()).collect(),
+            };
+            let guild = submission.guild;
+            let added = tokio::task::spawn_blocking(move || {
+                // another submission of the same link got approved first
+                if finder.nub_finder.get(&nub.source, Some(guild))?.is_some() {
+                    return Ok(false);
+                }
+                finder.nub_finder.add_guild_nub(guild, nub).map(|()| true)
+            }).await?;
+            match added {
+                Ok(true) => locale.format("<@{}>'s nub was approved{}", &[&submission.user, &moderator]),
+                Ok(false) => locale.format("<@{}>'s nub was already added", &[&submission.user]),
+                Err(e) => {
+                    state.submissions.update(id, |pending| {
+                        *pending = submission;
+                    });
+                    return Err(e);
+                }
+            }
+        }
+        _ => locale.format("<@{}>'s nub was rejected{}", &[&submission.user, &moderator]),
+    };
+    let store = state.clone();
+    tokio::task::spawn_blocking(move || store.submissions.flush()).await??;
+
+    respond(
+        interaction,
+        http,
+        InteractionResponseType::UpdateMessage,
+        InteractionResponseDataBuilder::new()
+            .content(content)
+            .allowed_mentions(AllowedMentions::default())
+            .components([])
+            .build()
+    ).await
+}
+
+fn text_input(
+    custom_id: &str,
+    label: &str,
+    style: TextInputStyle,
+    value: Option<String>
+) -> Component {
+    Component::ActionRow(ActionRow {
+        components: vec![
+            Component::TextInput(TextInput {
+                custom_id: custom_id.to_string(),
+                label: label.to_string(),
+                max_length: None,
+                min_length: Some(1),
+                placeholder: None,
+                required: Some(true),
+                style,
+                value,
+            }),
+        ],
+    })
+}
+
+fn button(custom_id: String, label: &str, style: ButtonStyle) -> Component {
+    Component::Button(Button {
+        custom_id: Some(custom_id),
+        disabled: false,
+        emoji: None,
+        label: Some(label.to_string()),
+        style,
+        url: None,
+        sku_id: None,
+    })
+}
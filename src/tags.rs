@@ -0,0 +1,254 @@
+//! Moderator tag editing for catalog nubs.
+//!
+//! The tag button on `/nub` replies opens a modal with the nub's tags and
+//! content rating. Edits are kept as overrides in [`crate::nub::NubFinder`]
+//! and can be exported with `/nub-tags-export` to be sent upstream. Servers
+//! export their own edits, and only the catalog's owner gets everyone's.
+//!
+//! Moderators can edit the nubs added to their server, and only the catalog's
+//! owner (`catalog.owner`) the ones every server shares.
+
+use std::{ error::Error, sync::Arc };
+
+use twilight_http::Client as HttpClient;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::{
+    application::interaction::{ modal::ModalInteractionData, Interaction },
+    channel::message::{
+        component::{ ActionRow, TextInput, TextInputStyle },
+        Component,
+        MessageFlags,
+    },
+    guild::Permissions,
+    http::{
+        attachment::Attachment,
+        interaction::InteractionResponseType,
+    },
+};
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::{
+    locale::Locale,
+    nub::{ split_keywords, Edited, Editor, TagOverride, GLOBAL },
+    rating::ContentRating,
+    response::{ ephemeral, respond },
+    AppState,
+};
+
+pub const EDIT_ID: &str = "nub-tags";
+
+const TAGS_INPUT_ID: &str = "tags";
+const RATING_INPUT_ID: &str = "rating";
+
+fn manage_messages() -> Permissions {
+    Permissions::MANAGE_MESSAGES
+}
+
+#[derive(CreateCommand, CommandModel)]
+#[command(
+    name = "nub-tags-export",
+    desc = "download the tag edits made here so they can be sent upstream",
+    default_permissions = "manage_messages",
+    contexts = "guild"
+)]
+pub struct NubTagsExportCommand;
+
+#[derive(serde::Serialize)]
+struct ExportedOverride {
+    source: String,
+    /// The guild a guild nub was added to, left out for catalog nubs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    guild: Option<u64>,
+    #[serde(flatten)]
+    tags: TagOverride,
+}
+
+/// Open the tag editor for the nub shown in the message.
+pub async fn handle_component(
+    interaction: &Interaction,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let locale = Locale::of(interaction);
+    if editor(interaction, &state).is_none() {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("only moderators can edit tags"))
+        ).await;
+    }
+
+    let indexed = match shown_url(interaction) {
+        Some(url) => {
+            let finder = state.clone();
+            let guild = interaction.guild_id.map(|id| id.get());
+            tokio::task::spawn_blocking(move || {
+                let Some((_, keywords)) = finder.nub_finder.get(&url, guild)? else {
+                    return Ok::<_, Box<dyn Error + Send + Sync>>(None);
+                };
+                let rating = finder.nub_finder.rating(&url, guild)?.unwrap_or_default();
+                Ok(Some((keywords, rating)))
+            }).await??
+        }
+        None => None,
+    };
+    let Some((keywords, rating)) = indexed else {
+        return respond(
+            interaction,
+            http,
+            InteractionResponseType::ChannelMessageWithSource,
+            ephemeral(locale.text("that nub isn't in the catalog"))
+        ).await;
+    };
+
+    let data = InteractionResponseDataBuilder::new()
+        .custom_id(EDIT_ID)
+        .title(locale.text("edit tags"))
+        .components([
+            Component::ActionRow(ActionRow {
+                components: vec![
+                    Component::TextInput(TextInput {
+                        custom_id: TAGS_INPUT_ID.to_string(),
+                        label: locale.text("tags, separated by commas").to_string(),
+                        max_length: None,
+                        min_length: Some(1),
+                        placeholder: None,
+                        required: Some(true),
+                        style: TextInputStyle::Paragraph,
+                        value: Some(keywords),
+                    }),
+                ],
+            }),
+            Component::ActionRow(ActionRow {
+                components: vec![
+                    Component::TextInput(TextInput {
+                        custom_id: RATING_INPUT_ID.to_string(),
+                        label: locale.text("rating: safe, edgy or nsfw").to_string(),
+                        max_length: Some(4),
+                        min_length: Some(4),
+                        placeholder: None,
+                        required: Some(true),
+                        style: TextInputStyle::Short,
+                        value: Some(rating.value().to_string()),
+                    }),
+                ],
+            }),
+        ])
+        .build();
+
+    respond(interaction, http, InteractionResponseType::Modal, data).await
+}
+
+/// Save the edited tags.
+pub async fn handle_modal(
+    interaction: &Interaction,
+    modal: ModalInteractionData,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut tags = Vec::new();
+    let mut rating = None;
+    for input in modal.components.into_iter().flat_map(|row| row.components) {
+        let value = input.value.unwrap_or_default();
+        match input.custom_id.as_str() {
+            TAGS_INPUT_ID => tags = split_keywords(&value),
+            RATING_INPUT_ID => rating = ContentRating::parse(&value),
+            _ => {}
+        }
+    }
+
+    let content = match (shown_url(interaction), rating, editor(interaction, &state)) {
+        (_, _, None) => "only moderators can edit tags",
+        _ if tags.is_empty() => "a nub needs at least one tag to be found",
+        (_, None, _) => "the rating has to be safe, edgy or nsfw",
+        (Some(url), Some(rating), Some(editor)) => {
+            let finder = state.clone();
+            let guild = interaction.guild_id.map(|id| id.get());
+            let edited = tokio::task::spawn_blocking(move || {
+                let shown = finder.nub_finder.rating(&url, guild)?;
+                let mut edited = finder.nub_finder.edit_tags(&url, tags, guild, editor)?;
+                // left as it was shown, the rating keeps following the tags
+                if edited == Edited::Saved && shown != Some(rating) {
+                    edited = finder.nub_finder.set_rating(&url, rating, guild, editor)?;
+                }
+                Ok::<_, Box<dyn Error + Send + Sync>>(edited)
+            }).await??;
+            match edited {
+                Edited::Saved => "tags saved",
+                Edited::Missing => "that nub isn't in the catalog",
+                Edited::Forbidden => "only the catalog's owner can edit nubs every server shares",
+            }
+        }
+        (None, _, _) => "there's no nub to edit here",
+    };
+
+    respond(
+        interaction,
+        http,
+        InteractionResponseType::ChannelMessageWithSource,
+        ephemeral(Locale::of(interaction).text(content))
+    ).await
+}
+
+/// Send the tag overrides as JSON, one entry per nub.
+pub async fn handle_export(
+    interaction: &Interaction,
+    http: &HttpClient,
+    state: Arc<AppState>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let guild = match editor(interaction, &state) {
+        Some(Editor::Owner) => None,
+        _ => Some(interaction.guild_id.map_or(GLOBAL, |id| id.get())),
+    };
+    let overrides = state.nub_finder
+        .tag_overrides()
+        .into_iter()
+        .filter(|((edited, _), _)| guild.is_none_or(|guild| *edited == guild))
+        .map(|((edited, source), tags)| ExportedOverride {
+            source,
+            guild: Some(edited).filter(|edited| *edited != GLOBAL),
+            tags,
+        })
+        .collect::<Vec<_>>();
+
+    let locale = Locale::of(interaction);
+    let data = if overrides.is_empty() {
+        ephemeral(locale.text("no tags have been edited yet"))
+    } else {
+        InteractionResponseDataBuilder::new()
+            .content(locale.format("{} edited nubs", &[&overrides.len()]))
+            .attachments([
+                Attachment::from_bytes(
+                    "tag_overrides.json".to_string(),
+                    serde_json::to_vec_pretty(&overrides)?,
+                    1
+                ),
+            ])
+            .flags(MessageFlags::EPHEMERAL)
+            .build()
+    };
+
+    respond(interaction, http, InteractionResponseType::ChannelMessageWithSource, data).await
+}
+
+/// Who's editing, if they can edit anything: the catalog's owner, or a
+/// moderator of the server it's used in.
+fn editor(interaction: &Interaction, state: &AppState) -> Option<Editor> {
+    if state.config.catalog.owner.is_some() && interaction.author_id().map(|id| id.get()) == state.config.catalog.owner {
+        return Some(Editor::Owner);
+    }
+    let is_moderator = interaction.member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.contains(Permissions::MANAGE_MESSAGES));
+    interaction.guild_id.filter(|_| is_moderator).map(|guild| Editor::Guild(guild.get()))
+}
+
+/// The URL of the nub in the message the button or modal came from.
+fn shown_url(interaction: &Interaction) -> Option<String> {
+    interaction.message
+        .as_ref()
+        .and_then(|message| message.embeds.first())
+        .and_then(crate::media::shown_url)
+}
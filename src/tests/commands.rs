@@ -0,0 +1,592 @@
+use serde_json::{ json, Value };
+use twilight_model::guild::Permissions;
+
+use super::*;
+use crate::{ nub::{ Edited, Editor }, rating::ContentRating };
+
+const EPHEMERAL: u64 = 1 << 6;
+
+/// Choice values offered by an autocomplete response.
+fn choice_values(response: &Request) -> Vec<String> {
+    assert_eq!(response.body["type"], 8);
+    response.body["data"]["choices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|choice| choice["value"].as_str().unwrap().to_string())
+        .collect()
+}
+
+fn content(response: &Request) -> &str {
+    response.body["data"]["content"].as_str().unwrap_or_default()
+}
+
+fn is_ephemeral(response: &Request) -> bool {
+    response.body["data"]["flags"].as_u64().is_some_and(|flags| flags & EPHEMERAL != 0)
+}
+
+/// Check a command was deferred and then finished with one file, and
+/// return the file.
+fn deferred_file(requests: &[Request]) -> (String, Vec<u8>) {
+    assert_eq!(requests.len(), 2, "expected a deferral and an edit, got {:#?}", requests);
+    assert_eq!(requests[0].body["type"], 5);
+    assert_eq!(requests[1].method, "PATCH");
+    assert_eq!(requests[1].path, format!("webhooks/{}/{}/messages/@original", APPLICATION_ID, TOKEN));
+    assert_eq!(requests[1].files.len(), 1);
+    requests[1].files[0].clone()
+}
+
+#[tokio::test]
+async fn four_sends_the_picked_variant() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("four", json!([string("variant", "mc-freakery")]))).await;
+
+    assert_eq!(response.body["type"], 4);
+    assert_eq!(content(&response), "https://aweirddev.github.io/emojis/four-mcfreakery.gif");
+}
+
+#[tokio::test]
+async fn four_without_a_variant_sends_one() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("four", json!([]))).await;
+
+    assert_eq!(response.body["type"], 4);
+    assert!(!content(&response).is_empty() || !response.files.is_empty());
+}
+
+#[tokio::test]
+async fn four_song_uploads_the_mp3() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("four", json!([string("variant", "song")]))).await;
+
+    assert_eq!(response.body["type"], 4);
+    assert_eq!(response.body["data"]["attachments"][0]["filename"], "four.mp3");
+    assert_eq!(response.files.len(), 1);
+    assert_eq!(response.files[0].0, "four.mp3");
+    assert_eq!(response.files[0].1, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/four.mp3")));
+}
+
+#[tokio::test]
+async fn four_autocomplete_filters_variants() {
+    let harness = Harness::new().await;
+    let response = harness.respond(autocomplete("four", "variant", "NUB")).await;
+
+    assert_eq!(choice_values(&response), ["silly-nub", "silly-nub-n"]);
+}
+
+#[tokio::test]
+async fn nub_sends_the_best_match() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("nub", json!([string("query", "sleepy")]))).await;
+
+    assert_eq!(response.body["type"], 4);
+    assert_eq!(response.body["data"]["embeds"][0]["image"]["url"], harness.nub_url("nubs/sleepy.png"));
+    assert!(!response.body["data"]["components"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn nub_sends_an_autocompleted_pick() {
+    let harness = Harness::new().await;
+    let url = harness.nub_url("nubs/dance.png");
+    let response = harness.respond(command("nub", json!([string("query", &format!("nub:{}", url))]))).await;
+
+    assert_eq!(response.body["data"]["embeds"][0]["image"]["url"], url);
+}
+
+#[tokio::test]
+async fn nub_says_when_nothing_matches() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("nub", json!([string("query", "xylophone")]))).await;
+
+    assert_eq!(content(&response), "i couldn't find that nub :(");
+}
+
+#[tokio::test]
+async fn nub_leaves_out_nsfw_outside_nsfw_channels() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("nub", json!([string("query", "lewd")]))).await;
+    assert_eq!(content(&response), "i couldn't find that nub :(");
+
+    let mut nsfw = command("nub", json!([string("query", "lewd")]));
+    nsfw["channel"]["nsfw"] = json!(true);
+    let settings = command(
+        "four-config",
+        json!([{ "name": "rating", "type": 1, "options": [string("rating", "nsfw")] }])
+    );
+    harness.respond(with_permissions(settings, Permissions::MANAGE_GUILD.bits())).await;
+    let response = harness.respond(nsfw).await;
+    assert_eq!(response.body["data"]["embeds"][0]["image"]["url"], harness.nub_url("nubs/lewd.png"));
+}
+
+#[tokio::test]
+async fn nub_autocomplete_searches_the_catalog() {
+    let harness = Harness::new().await;
+    let response = harness.respond(autocomplete("nub", "query", "party")).await;
+
+    assert_eq!(choice_values(&response), [format!("nub:{}", harness.nub_url("nubs/dance.png"))]);
+    assert_eq!(response.body["data"]["choices"][0]["name"], "dance, party");
+}
+
+#[tokio::test]
+async fn nub_autocomplete_offers_favorites_when_empty() {
+    let harness = Harness::new().await;
+    let response = harness.respond(autocomplete("nub", "query", " ")).await;
+    assert!(choice_values(&response).is_empty());
+
+    let url = harness.nub_url("nubs/sleepy.png");
+    harness.state.favorites.update(USER_ID.parse().unwrap(), |favorites| favorites.push(url.clone()));
+    let response = harness.respond(autocomplete("nub", "query", "")).await;
+    assert_eq!(choice_values(&response), [format!("nub:{}", url)]);
+}
+
+#[tokio::test]
+async fn nub_autocomplete_keeps_a_pick() {
+    let harness = Harness::new().await;
+    let response = harness.respond(autocomplete("nub", "query", "nub:https://example.com/nub.gif")).await;
+
+    assert_eq!(choice_values(&response), ["nub:https://example.com/nub.gif"]);
+}
+
+#[tokio::test]
+async fn simple_commands_send_their_gif() {
+    let harness = Harness::new().await;
+    for (name, url) in [
+        ("unzip", crate::UnzipCommand::URL),
+        ("you", crate::YouCommand::URL),
+        ("oil-up", crate::OilUpCommand::URL),
+        ("jump", crate::JumpCommand::URL),
+        ("sad-phone", crate::SadPhoneCommand::URL),
+        ("huge", crate::HugeCommand::URL),
+        ("hello", crate::HelloCommand::URL),
+    ] {
+        let response = harness.respond(command(name, json!([]))).await;
+        assert_eq!(content(&response), url, "/{}", name);
+    }
+}
+
+#[tokio::test]
+async fn rate_ragebait_sends_the_rating() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("rate-ragebait", json!([string("rating", "seven")]))).await;
+
+    assert_eq!(content(&response), crate::RagebaitRating::Seven.url());
+}
+
+#[tokio::test]
+async fn four_config_needs_manage_server() {
+    let harness = Harness::new().await;
+    let show = json!([{ "name": "show", "type": 1, "options": [] }]);
+
+    let response = harness.respond(command("four-config", show.clone())).await;
+    assert_eq!(content(&response), "you need the Manage Server permission for this");
+
+    let mut dm = command("four-config", show);
+    dm.as_object_mut().unwrap().remove("guild_id");
+    let response = harness.respond(dm).await;
+    assert_eq!(content(&response), "this only works in servers");
+}
+
+#[tokio::test]
+async fn four_config_disables_and_enables_commands() {
+    let harness = Harness::new().await;
+    let configure = |subcommand: &str, options: Value| with_permissions(
+        command("four-config", json!([{ "name": subcommand, "type": 1, "options": options }])),
+        Permissions::MANAGE_GUILD.bits()
+    );
+
+    let response = harness.respond(configure("disable", json!([string("command", "/nub")]))).await;
+    assert_eq!(content(&response), "`nub` is now disabled");
+    assert!(is_ephemeral(&response));
+
+    let response = harness.respond(command("nub", json!([string("query", "sleepy")]))).await;
+    assert_eq!(content(&response), "this command is disabled here");
+    // autocomplete still answers, it can't be refused
+    let response = harness.respond(autocomplete("nub", "query", "sleepy")).await;
+    assert_eq!(choice_values(&response).len(), 1);
+
+    let response = harness.respond(configure("show", json!([]))).await;
+    assert!(content(&response).contains("nub"), "{}", content(&response));
+
+    let response = harness.respond(configure("enable", json!([string("command", "nub")]))).await;
+    assert_eq!(content(&response), "`nub` is now enabled");
+    let response = harness.respond(command("nub", json!([string("query", "sleepy")]))).await;
+    assert!(response.body["data"]["embeds"].is_array());
+
+    let response = harness.respond(configure("disable", json!([string("command", "four-config")]))).await;
+    assert_eq!(content(&response), "nice try");
+    let response = harness.respond(configure("disable", json!([string("command", "nope")]))).await;
+    assert_eq!(content(&response), "there's no command called `nope`");
+}
+
+#[tokio::test]
+async fn four_config_sets_the_default_variant_and_rating() {
+    let harness = Harness::new().await;
+    let configure = |subcommand: &str, options: Value| with_permissions(
+        command("four-config", json!([{ "name": subcommand, "type": 1, "options": options }])),
+        Permissions::MANAGE_GUILD.bits()
+    );
+
+    let response = harness.respond(configure("default-variant", json!([string("variant", "mc-freakery")]))).await;
+    assert_eq!(content(&response), "`/four` now defaults to `mc-freakery`");
+    let response = harness.respond(command("four", json!([]))).await;
+    assert_eq!(content(&response), "https://aweirddev.github.io/emojis/four-mcfreakery.gif");
+
+    let response = harness.respond(configure("rating", json!([string("rating", "safe")]))).await;
+    assert_eq!(content(&response), "content is now limited to `safe`");
+    let response = harness.respond(autocomplete("four", "variant", "nub")).await;
+    assert_eq!(choice_values(&response), ["silly-nub"]);
+
+    let response = harness.respond(configure("channel", json!([{ "name": "channel", "type": 7, "value": "401" }]))).await;
+    assert_eq!(content(&response), "<#401> is now allowed");
+    let response = harness.respond(command("hello", json!([]))).await;
+    assert_eq!(content(&response), "i'm not allowed to post in this channel");
+
+    let response = harness.respond(configure("reset", json!([]))).await;
+    assert_eq!(content(&response), "settings are back to the defaults");
+    let response = harness.respond(command("hello", json!([]))).await;
+    assert_eq!(content(&response), crate::HelloCommand::URL);
+}
+
+#[tokio::test]
+async fn nub_favorites_lists_favorites() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("nub-favorites", json!([]))).await;
+    assert_eq!(content(&response), "no favorites yet, hit ⭐ on a /nub to save one");
+    assert!(is_ephemeral(&response));
+
+    let url = harness.nub_url("nubs/sleepy.png");
+    harness.state.favorites.update(USER_ID.parse().unwrap(), |favorites| favorites.push(url.clone()));
+    let response = harness.respond(command("nub-favorites", json!([]))).await;
+    let description = response.body["data"]["embeds"][0]["description"].as_str().unwrap();
+    assert_eq!(description, format!("1. [sleepy, bed, tired]({})", url));
+}
+
+#[tokio::test]
+async fn four_stats_counts_commands_and_picks() {
+    let harness = Harness::new().await;
+    harness.respond(command("hello", json!([]))).await;
+    harness.respond(command("hello", json!([]))).await;
+    harness.respond(command("four", json!([string("variant", "silly-nub")]))).await;
+    harness.send(component("nub-reroll:sleepy")).await;
+
+    let response = harness.respond(command("four-stats", json!([string("window", "day")]))).await;
+    let embed = &response.body["data"]["embeds"][0];
+    assert_eq!(embed["title"], "four stats (day)");
+    assert_eq!(embed["fields"][0]["value"], "1. `/hello` (2)\n2. `/four` (1)\n3. `/four-stats` (1)");
+    assert_eq!(embed["fields"][1]["value"], format!(
+        "1. `/four silly-nub` (1)\n2. [nub]({}) (1)",
+        harness.nub_url("nubs/sleepy.png")
+    ));
+    assert_eq!(embed["fields"][2]["value"], "1. `nub-reroll` (1)");
+
+    // other servers and DMs have their own counts
+    let mut elsewhere = command("four-stats", json!([]));
+    elsewhere["guild_id"] = json!("301");
+    let response = harness.respond(elsewhere).await;
+    assert_eq!(response.body["data"]["embeds"][0]["fields"][0]["value"], "1. `/four-stats` (1)");
+}
+
+#[tokio::test]
+async fn only_the_owner_sees_the_busiest_servers() {
+    let harness = Harness::owned_by("501").await;
+    harness.respond(command("hello", json!([]))).await;
+
+    let response = harness.respond(command("four-stats", json!([]))).await;
+    assert_eq!(response.body["data"]["embeds"][0]["fields"].as_array().unwrap().len(), 3);
+    assert!(!is_ephemeral(&response));
+
+    let mut owner = command("four-stats", json!([]));
+    owner["member"]["user"] = user("501");
+    let response = harness.respond(owner).await;
+    assert_eq!(response.body["data"]["embeds"][0]["fields"][3]["value"], format!("1. {} (3)", GUILD_ID));
+    assert!(is_ephemeral(&response));
+}
+
+#[tokio::test]
+async fn four_on_draws_the_hand_on_an_image() {
+    let harness = Harness::new().await;
+    let url = harness.discord.serve("uploads/cat.png", png());
+    let mut interaction = command("four-on", json!([
+        { "name": "image", "type": 11, "value": "700" },
+        string("position", "center"),
+    ]));
+    interaction["data"]["resolved"] = json!({
+        "attachments": {
+            "700": {
+                "id": "700",
+                "filename": "cat.png",
+                "size": 100,
+                "url": url,
+                "proxy_url": url,
+            },
+        },
+    });
+
+    let (filename, bytes) = deferred_file(&harness.send(interaction).await);
+    assert_eq!(filename, "four.png");
+    let image = image::load_from_memory(&bytes).unwrap();
+    assert_eq!((image.width(), image.height()), (64, 48));
+}
+
+/// `/four-on` with an uploaded image.
+fn four_on_upload(url: &str, size: u64) -> Value {
+    let mut interaction = command("four-on", json!([{ "name": "image", "type": 11, "value": "700" }]));
+    interaction["data"]["resolved"] = json!({
+        "attachments": { "700": { "id": "700", "filename": "cat.png", "size": size, "url": url, "proxy_url": url } },
+    });
+    interaction
+}
+
+#[tokio::test]
+async fn four_on_says_when_the_image_is_gone() {
+    let harness = Harness::new().await;
+    let url = harness.nub_url("nubs/sleepy.png").replace("nubs/sleepy.png", "missing.png");
+
+    let requests = harness.send(four_on_upload(&url, 100)).await;
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].body["content"], "i couldn't download that image :(");
+}
+
+#[tokio::test]
+async fn four_on_refuses_what_it_cant_draw_on() {
+    let harness = Harness::new().await;
+    let url = harness.discord.serve("uploads/cat.png", b"not an image".to_vec());
+
+    let response = harness.respond(four_on_upload(&url, 100 * 1024 * 1024)).await;
+    assert_eq!(content(&response), "that's too big, images can be up to 25 MB");
+
+    let requests = harness.send(four_on_upload(&url, 100)).await;
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].body["content"], "i couldn't draw on that :(");
+    // uploads can say they're smaller than they are
+    let url = harness.discord.serve("uploads/huge.png", vec![0; crate::media::MAX_DOWNLOAD_SIZE as usize + 1]);
+    let requests = harness.send(four_on_upload(&url, 100)).await;
+    assert_eq!(requests[1].body["content"], "i couldn't download that image :(");
+}
+
+#[tokio::test]
+async fn nub_caption_draws_the_text() {
+    let harness = Harness::new().await;
+    let interaction = command("nub-caption", json!([string("nub", "sleepy"), string("top", "when the")]));
+
+    let (filename, bytes) = deferred_file(&harness.send(interaction).await);
+    assert_eq!(filename, "nub.png");
+    assert_ne!(bytes, png());
+}
+
+#[tokio::test]
+async fn nub_caption_says_when_it_cant_draw() {
+    let harness = Harness::new().await;
+    let caption = || command("nub-caption", json!([string("nub", "sleepy"), string("top", "when the")]));
+
+    harness.discord.serve("nubs/sleepy.png", b"not an image".to_vec());
+    let requests = harness.send(caption()).await;
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].body["content"], "i couldn't draw on that :(");
+
+    let mut gif = Vec::new();
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+        let frames = (0..301).map(|_| image::Frame::new(RgbaImage::new(1, 1)));
+        encoder.encode_frames(frames).unwrap();
+    }
+    harness.discord.serve("nubs/sleepy.png", gif);
+    let requests = harness.send(caption()).await;
+    assert_eq!(requests[1].body["content"], "that's too big for me to draw on :(");
+}
+
+#[tokio::test]
+async fn nub_caption_needs_text() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("nub-caption", json!([string("nub", "sleepy")]))).await;
+
+    assert_eq!(content(&response), "give me some text to put on it");
+    assert!(is_ephemeral(&response));
+}
+
+#[tokio::test]
+async fn nub_caption_autocompletes_nubs() {
+    let harness = Harness::new().await;
+    let response = harness.respond(autocomplete("nub-caption", "nub", "tired")).await;
+
+    assert_eq!(choice_values(&response), [format!("nub:{}", harness.nub_url("nubs/sleepy.png"))]);
+}
+
+#[tokio::test]
+async fn nub_add_asks_for_tags() {
+    let harness = Harness::new().await;
+    let response = harness.respond(command("nub-add", json!([string("url", "https://example.com/new.gif")]))).await;
+
+    assert_eq!(response.body["type"], 9);
+    assert_eq!(response.body["data"]["custom_id"], crate::submissions::SUBMIT_ID);
+    assert_eq!(response.body["data"]["components"][0]["components"][0]["value"], "https://example.com/new.gif");
+
+    let response = harness.respond(command("nub-add", json!([]))).await;
+    assert_eq!(content(&response), "give me a link or upload an image");
+}
+
+#[tokio::test]
+async fn nub_add_refuses_existing_nubs() {
+    let harness = Harness::new().await;
+    let url = harness.nub_url("nubs/sleepy.png");
+    let response = harness.respond(modal(crate::submissions::SUBMIT_ID, &[("url", &url), ("tags", "cat")])).await;
+
+    assert_eq!(content(&response), "that's already a nub");
+    assert!(harness.state.submissions.entries().is_empty());
+}
+
+/// `/nub-add` with an uploaded image.
+fn nub_add_upload(url: &str, size: u64) -> Value {
+    let mut interaction = command("nub-add", json!([{ "name": "image", "type": 11, "value": "700" }]));
+    interaction["data"]["resolved"] = json!({
+        "attachments": {
+            "700": { "id": "700", "filename": "new.png", "size": size, "url": url, "proxy_url": url },
+        },
+    });
+    interaction
+}
+
+#[tokio::test]
+async fn nub_add_keeps_a_copy_of_uploads() {
+    let harness = Harness::new().await;
+    let url = harness.nub_url("attachments/new.png");
+    let response = harness.respond(nub_add_upload(&url, 100)).await;
+    assert_eq!(content(&response), "uploads aren't kept here, send a link instead");
+
+    let harness = Harness::with_media_cache().await;
+    let url = harness.nub_url("attachments/new.png");
+    let response = harness.respond(nub_add_upload(&url, 20 * 1024 * 1024)).await;
+    assert_eq!(content(&response), "that's too big, uploads can be up to 10 MB");
+
+    let response = harness.respond(nub_add_upload(&url, 100)).await;
+    assert_eq!(response.body["data"]["components"][0]["components"][0]["value"], url.as_str());
+    let media = harness.state.media.as_ref().unwrap();
+    for _ in 0..100 {
+        if media.has(&url) {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("{} wasn't cached", url);
+}
+
+#[tokio::test]
+async fn tag_edits_are_limited_to_the_guild_unless_owner() {
+    let manage_messages = Permissions::MANAGE_MESSAGES.bits();
+    let edit = |url: &str, tags: &str, rating: &str| {
+        showing(modal(crate::tags::EDIT_ID, &[("tags", tags), ("rating", rating)]), url)
+    };
+
+    let harness = Harness::new().await;
+    let url = harness.nub_url("nubs/dance.png");
+    let response = harness.respond(edit(&url, "dance", "safe")).await;
+    assert_eq!(content(&response), "only moderators can edit tags");
+    let response = harness.respond(with_permissions(edit(&url, "dance", "safe"), manage_messages)).await;
+    assert_eq!(content(&response), "only the catalog's owner can edit nubs every server shares");
+    assert!(harness.state.nub_finder.tag_overrides().is_empty());
+
+    let nub = Nub { source: "https://example.com/ours.gif".into(), tags: vec!["ours".into()] };
+    harness.state.nub_finder.add_guild_nub(GUILD_ID.parse().unwrap(), nub).unwrap();
+    let response = harness.respond(with_permissions(edit("https://example.com/ours.gif", "ours, mine", "edgy"), manage_messages)).await;
+    assert_eq!(content(&response), "tags saved");
+    assert_eq!(harness.state.nub_finder.rating("https://example.com/ours.gif", Some(GUILD_ID.parse().unwrap())).unwrap(), Some(ContentRating::Edgy));
+
+    let harness = Harness::owned_by(USER_ID).await;
+    let url = harness.nub_url("nubs/dance.png");
+    let response = harness.respond(edit(&url, "dance, nsfw", "safe")).await;
+    assert_eq!(content(&response), "tags saved");
+    // the rating was left as shown, so it follows the new tags
+    assert_eq!(harness.state.nub_finder.rating(&url, None).unwrap(), Some(ContentRating::Nsfw));
+}
+
+#[tokio::test]
+async fn nub_tags_export_sends_the_edits() {
+    let harness = Harness::owned_by(USER_ID).await;
+    let response = harness.respond(command("nub-tags-export", json!([]))).await;
+    assert_eq!(content(&response), "no tags have been edited yet");
+
+    let url = harness.nub_url("nubs/dance.png");
+    let tags = vec!["dance".to_string(), "groove".to_string()];
+    assert_eq!(harness.state.nub_finder.edit_tags(&url, tags, None, Editor::Owner).unwrap(), Edited::Saved);
+    let response = harness.respond(command("nub-tags-export", json!([]))).await;
+    assert_eq!(content(&response), "1 edited nubs");
+    assert_eq!(response.files[0].0, "tag_overrides.json");
+    let exported = serde_json::from_slice::<Value>(&response.files[0].1).unwrap();
+    assert_eq!(exported[0]["source"], url);
+    assert!(exported[0].get("guild").is_none());
+}
+
+#[tokio::test]
+async fn nub_tags_export_only_has_the_servers_own_edits() {
+    let harness = Harness::owned_by("501").await;
+    let url = harness.nub_url("nubs/dance.png");
+    let tags = vec!["dance".to_string(), "groove".to_string()];
+    harness.state.nub_finder.edit_tags(&url, tags, None, Editor::Owner).unwrap();
+    let response = harness.respond(command("nub-tags-export", json!([]))).await;
+    assert_eq!(content(&response), "no tags have been edited yet");
+
+    let guild = GUILD_ID.parse().unwrap();
+    let nub = Nub { source: "https://example.com/ours.gif".into(), tags: vec!["ours".into()] };
+    harness.state.nub_finder.add_guild_nub(guild, nub).unwrap();
+    let tags = vec!["ours".to_string(), "mine".to_string()];
+    harness.state.nub_finder.edit_tags("https://example.com/ours.gif", tags, Some(guild), Editor::Guild(guild)).unwrap();
+    let response = harness.respond(command("nub-tags-export", json!([]))).await;
+    assert_eq!(content(&response), "1 edited nubs");
+    let exported = serde_json::from_slice::<Value>(&response.files[0].1).unwrap();
+    assert_eq!(exported[0]["source"], "https://example.com/ours.gif");
+    assert_eq!(exported[0]["guild"], guild);
+
+    let mut owner = command("nub-tags-export", json!([]));
+    owner["member"]["user"] = user("501");
+    let response = harness.respond(owner).await;
+    assert_eq!(content(&response), "2 edited nubs");
+}
+
+#[tokio::test]
+async fn message_context_menus_offer_choices() {
+    let harness = Harness::new().await;
+    let target = |name: &str| interaction(2, json!({
+        "id": "1",
+        "name": name,
+        "type": 3,
+        "target_id": MESSAGE_ID,
+        "resolved": { "messages": { MESSAGE_ID: message("ragebait") } },
+    }));
+
+    let response = harness.respond(target("Rate ragebait")).await;
+    assert!(is_ephemeral(&response));
+    let select = &response.body["data"]["components"][0]["components"][0];
+    assert_eq!(select["custom_id"], format!("rate-ragebait:{}:{}", CHANNEL_ID, MESSAGE_ID));
+    assert_eq!(select["options"].as_array().unwrap().len(), 2);
+
+    let response = harness.respond(target("Reply with four")).await;
+    let rows = response.body["data"]["components"].as_array().unwrap();
+    assert_eq!(rows[0]["components"][0]["custom_id"], format!("reply-four:{}:{}", CHANNEL_ID, MESSAGE_ID));
+    assert_eq!(rows[1]["components"][0]["custom_id"], format!("reply-nub:{}:{}", CHANNEL_ID, MESSAGE_ID));
+}
+
+#[tokio::test]
+async fn user_context_menu_offers_nubs() {
+    let harness = Harness::new().await;
+    let response = harness.respond(interaction(2, json!({
+        "id": "1",
+        "name": "Nub this user",
+        "type": 2,
+        "target_id": "501",
+    }))).await;
+
+    assert!(is_ephemeral(&response));
+    let buttons = response.body["data"]["components"][0]["components"].as_array().unwrap();
+    assert!(buttons.iter().any(|button| button["custom_id"] == "nub-user-random:501:1"));
+}
+
+#[tokio::test]
+async fn replies_follow_the_user_locale() {
+    let harness = Harness::new().await;
+    let mut interaction = command("nub", json!([string("query", "xylophone")]));
+    interaction["locale"] = json!("es-ES");
+    let response = harness.respond(interaction).await;
+
+    assert_eq!(content(&response), crate::locale::Locale::new("es-ES").text("i couldn't find that nub :("));
+    assert_ne!(content(&response), "i couldn't find that nub :(");
+}
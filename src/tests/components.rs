@@ -0,0 +1,141 @@
+use serde_json::Value;
+use twilight_model::guild::Permissions;
+
+use super::*;
+use crate::{
+    buttons::{ DELETE_ID, REROLL_ID },
+    favorites::FAVORITE_ID,
+    rating::ContentRating,
+    submissions::{ APPROVE_ID, REJECT_ID, SUBMIT_ID },
+    tags::EDIT_ID,
+};
+
+const MANAGE_MESSAGES: u64 = Permissions::MANAGE_MESSAGES.bits();
+
+fn content(response: &Request) -> &str {
+    response.body["data"]["content"].as_str().unwrap_or_default()
+}
+
+/// The same interaction, from a different member.
+fn from_someone_else(mut interaction: Value) -> Value {
+    interaction["member"]["user"] = user("501");
+    interaction
+}
+
+/// The `custom_id`s of the buttons on a response.
+fn button_ids(response: &Request) -> Vec<String> {
+    response.body["data"]["components"][0]["components"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|button| button["custom_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn reroll_shows_another_match() {
+    let harness = Harness::new().await;
+    let shown = harness.nub_url("nubs/dance.png");
+    let response = harness.respond(showing(component(&format!("{}:sleepy", REROLL_ID)), &shown)).await;
+
+    assert_eq!(response.body["type"], 7);
+    assert_eq!(response.body["data"]["embeds"][0]["image"]["url"], harness.nub_url("nubs/sleepy.png"));
+}
+
+#[tokio::test]
+async fn only_the_invoker_can_reroll_or_delete() {
+    let harness = Harness::new().await;
+    for custom_id in [format!("{}:sleepy", REROLL_ID), DELETE_ID.to_string()] {
+        let response = harness.respond(from_someone_else(component(&custom_id))).await;
+        assert_eq!(content(&response), "that's not your nub :(");
+    }
+}
+
+#[tokio::test]
+async fn delete_removes_the_reply() {
+    let harness = Harness::new().await;
+    let requests = harness.send(component(DELETE_ID)).await;
+
+    assert_eq!(requests.len(), 2, "{:#?}", requests);
+    assert_eq!(requests[0].body["type"], 6);
+    assert_eq!(requests[1].method, "DELETE");
+    assert_eq!(requests[1].path, format!("webhooks/{}/{}/messages/@original", APPLICATION_ID, TOKEN));
+}
+
+#[tokio::test]
+async fn favorite_toggles_the_shown_nub() {
+    let harness = Harness::new().await;
+    let url = harness.nub_url("nubs/sleepy.png");
+    let favorites = || harness.state.favorites.get(&USER_ID.parse().unwrap()).unwrap_or_default();
+
+    let response = harness.respond(showing(component(FAVORITE_ID), &url)).await;
+    assert_eq!(content(&response), "saved to your favorites ⭐");
+    assert_eq!(favorites(), [url.as_str()]);
+
+    // anyone can save a nub they see, not just whoever asked for it
+    let response = harness.respond(from_someone_else(showing(component(FAVORITE_ID), &url))).await;
+    assert_eq!(content(&response), "saved to your favorites ⭐");
+
+    let response = harness.respond(showing(component(FAVORITE_ID), &url)).await;
+    assert_eq!(content(&response), "removed from your favorites");
+    assert!(favorites().is_empty());
+
+    let response = harness.respond(component(FAVORITE_ID)).await;
+    assert_eq!(content(&response), "there's no nub to save here");
+}
+
+#[tokio::test]
+async fn tag_button_opens_the_editor_for_the_shown_nub() {
+    let harness = Harness::owned_by(USER_ID).await;
+    let url = harness.nub_url("nubs/sleepy.png");
+
+    let response = harness.respond(from_someone_else(showing(component(EDIT_ID), &url))).await;
+    assert_eq!(content(&response), "only moderators can edit tags");
+
+    let response = harness.respond(showing(component(EDIT_ID), &url)).await;
+    assert_eq!(response.body["type"], 9);
+    assert_eq!(response.body["data"]["custom_id"], EDIT_ID);
+    let inputs = &response.body["data"]["components"];
+    let (tags, rating) = (&inputs[0]["components"][0]["value"], &inputs[1]["components"][0]["value"]);
+    assert_eq!(rating, "safe");
+
+    let mut tags = tags.as_str().unwrap().to_string();
+    tags.push_str(", nap");
+    let response = harness.respond(showing(modal(EDIT_ID, &[("tags", &tags), ("rating", "edgy")]), &url)).await;
+    assert_eq!(content(&response), "tags saved");
+    let (_, keywords) = harness.state.nub_finder.get(&url, None).unwrap().unwrap();
+    assert!(keywords.ends_with(", nap"), "{}", keywords);
+    assert_eq!(harness.state.nub_finder.rating(&url, None).unwrap(), Some(ContentRating::Edgy));
+}
+
+#[tokio::test]
+async fn moderators_approve_or_reject_submissions() {
+    let harness = Harness::new().await;
+    let submit = |url: &str| modal(SUBMIT_ID, &[("url", url), ("tags", "brand new")]);
+
+    let response = harness.respond(submit("https://example.com/new.gif")).await;
+    assert_eq!(response.body["data"]["embeds"][0]["image"]["url"], "https://example.com/new.gif");
+    let [approve, reject] = <[String; 2]>::try_from(button_ids(&response)).unwrap();
+    assert!(approve.starts_with(APPROVE_ID) && reject.starts_with(REJECT_ID));
+
+    let response = harness.respond(component(&approve)).await;
+    assert_eq!(content(&response), "only moderators can review nubs");
+
+    let response = harness.respond(with_permissions(component(&approve), MANAGE_MESSAGES)).await;
+    assert_eq!(response.body["type"], 7);
+    assert_eq!(content(&response), format!("<@{}>'s nub was approved by <@{}>", USER_ID, USER_ID));
+    let guild = Some(GUILD_ID.parse().unwrap());
+    let found = harness.state.nub_finder.search("brand new", guild, ContentRating::Safe).unwrap();
+    assert_eq!(found[0].0, "https://example.com/new.gif");
+    assert!(harness.state.nub_finder.search("brand new", None, ContentRating::Safe).unwrap().is_empty());
+
+    let response = harness.respond(with_permissions(component(&reject), MANAGE_MESSAGES)).await;
+    assert_eq!(content(&response), "someone already took care of this one");
+
+    let response = harness.respond(submit("https://example.com/other.gif")).await;
+    let reject = button_ids(&response).remove(1);
+    let response = harness.respond(with_permissions(component(&reject), MANAGE_MESSAGES)).await;
+    assert_eq!(content(&response), format!("<@{}>'s nub was rejected by <@{}>", USER_ID, USER_ID));
+    assert!(harness.state.nub_finder.get("https://example.com/other.gif", guild).unwrap().is_none());
+    assert!(harness.state.submissions.entries().is_empty());
+}
@@ -0,0 +1,266 @@
+//! Runs interactions through [`handle_event`] against [`MockDiscord`] and
+//! checks what gets sent back, without a network connection.
+
+mod commands;
+mod components;
+mod replay;
+
+use std::{ io::Cursor, path::{ Path, PathBuf }, sync::{ atomic::{ AtomicUsize, Ordering }, Arc } };
+
+use image::{ ImageFormat, Rgba, RgbaImage };
+use serde_json::{ json, Value };
+use twilight_cache_inmemory::DefaultInMemoryCache;
+use twilight_gateway::Event;
+use twilight_http::Client as HttpClient;
+use twilight_model::{ application::interaction::Interaction, gateway::payload::incoming::InteractionCreate };
+
+use crate::{
+    config::Config,
+    handle_event,
+    media::MediaCache,
+    mock::{ MockDiscord, Request },
+    nub::Nub,
+    variants::Variants,
+    AppState,
+};
+
+pub const APPLICATION_ID: &str = "100";
+pub const INTERACTION_ID: &str = "200";
+pub const TOKEN: &str = "interaction-token";
+pub const GUILD_ID: &str = "300";
+pub const CHANNEL_ID: &str = "400";
+pub const USER_ID: &str = "500";
+pub const MESSAGE_ID: &str = "600";
+
+/// What the finder searches in every test.
+pub const CATALOG: &[(&str, &[&str])] = &[
+    ("nubs/sleepy.png", &["sleepy", "bed", "tired"]),
+    ("nubs/dance.png", &["dance", "party"]),
+    ("nubs/lewd.png", &["nsfw", "lewd"]),
+];
+
+/// Tells apart the data directories of tests running at the same time.
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory for a test's files, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "four-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Settings with every store, and the catalog, in this directory.
+    pub fn config(&self) -> Config {
+        let mut config = Config::default();
+        config.catalog.path = self.0.join("four.bin");
+        config.storage.dir = self.0.clone();
+        config.search.writer_heap = 15_000_000;
+        config
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub struct Harness {
+    pub discord: MockDiscord,
+    pub state: Arc<AppState>,
+    http: Arc<HttpClient>,
+    _dir: TempDir,
+}
+
+impl Harness {
+    /// A bot with the fixture catalog, empty stores and every catalog image
+    /// served by the mock.
+    pub async fn new() -> Self {
+        Self::start(false, None).await
+    }
+
+    /// Like [`Harness::new`], with the media cache on.
+    pub async fn with_media_cache() -> Self {
+        Self::start(true, None).await
+    }
+
+    /// Like [`Harness::new`], with `user` owning the catalog.
+    pub async fn owned_by(user: &str) -> Self {
+        Self::start(false, Some(user.parse().unwrap())).await
+    }
+
+    async fn start(media_cache: bool, owner: Option<u64>) -> Self {
+        let discord = MockDiscord::start().await.unwrap();
+        let dir = TempDir::new();
+
+        let mut config = dir.config();
+        config.catalog.owner = owner;
+        let media = media_cache.then(|| Arc::new(MediaCache::new(dir.path().join("media")).unwrap()));
+        let state = AppState::new(config, DefaultInMemoryCache::new(), None, media, Variants::load().unwrap()).unwrap();
+        let nubs = CATALOG
+            .iter()
+            .map(|(path, tags)| Nub {
+                source: discord.serve(path, png()).into(),
+                tags: tags.iter().map(|&tag| tag.into()).collect(),
+            })
+            .collect();
+        state.nub_finder.commit(nubs).unwrap();
+
+        Self { http: Arc::new(discord.client()), discord, state: Arc::new(state), _dir: dir }
+    }
+
+    /// The URL a catalog nub is served at.
+    pub fn nub_url(&self, path: &str) -> String {
+        self.discord.serve(path, png())
+    }
+
+    /// Dispatch an interaction and return every request it made.
+    pub async fn send(&self, interaction: Value) -> Vec<Request> {
+        let interaction = serde_json::from_value::<Interaction>(interaction).unwrap();
+        let event = Event::InteractionCreate(Box::new(InteractionCreate(interaction)));
+        handle_event(event, self.http.clone(), self.state.clone()).await.unwrap();
+        self.discord.take()
+    }
+
+    /// Dispatch an interaction that's answered with a single response, and
+    /// return that response.
+    pub async fn respond(&self, interaction: Value) -> Request {
+        let mut requests = self.send(interaction).await;
+        assert_eq!(requests.len(), 1, "expected one response, got {:#?}", requests);
+        let response = requests.remove(0);
+        assert_eq!(response.method, "POST");
+        assert_eq!(response.path, format!("interactions/{}/{}/callback", INTERACTION_ID, TOKEN));
+        response
+    }
+}
+
+/// An interaction from a regular member in a guild text channel.
+pub fn interaction(kind: u8, data: Value) -> Value {
+    json!({
+        "id": INTERACTION_ID,
+        "application_id": APPLICATION_ID,
+        "type": kind,
+        "token": TOKEN,
+        "version": 1,
+        "authorizing_integration_owners": {},
+        "entitlements": [],
+        "guild_id": GUILD_ID,
+        "channel": { "id": CHANNEL_ID, "type": 0, "nsfw": false },
+        "member": {
+            "user": user(USER_ID),
+            "roles": [],
+            "joined_at": "2024-01-01T00:00:00.000000+00:00",
+            "deaf": false,
+            "mute": false,
+            "flags": 0,
+            "permissions": "0",
+        },
+        "locale": "en-US",
+        "guild_locale": "en-US",
+        "data": data,
+    })
+}
+
+/// A slash command being run.
+pub fn command(name: &str, options: Value) -> Value {
+    interaction(2, json!({ "id": "1", "name": name, "type": 1, "options": options }))
+}
+
+/// A slash command option being typed, for autocomplete.
+pub fn autocomplete(name: &str, option: &str, typed: &str) -> Value {
+    interaction(4, json!({
+        "id": "1",
+        "name": name,
+        "type": 1,
+        "options": [{ "name": option, "type": 3, "value": typed, "focused": true }],
+    }))
+}
+
+/// A button on a reply to a command [`USER_ID`] ran being clicked.
+pub fn component(custom_id: &str) -> Value {
+    let mut reply = message("");
+    reply["author"] = user(APPLICATION_ID);
+    reply["interaction_metadata"] = json!({
+        "id": "199",
+        "type": 2,
+        "user": user(USER_ID),
+        "authorizing_integration_owners": {},
+    });
+
+    let mut interaction = interaction(3, json!({ "custom_id": custom_id, "component_type": 2 }));
+    interaction["message"] = reply;
+    interaction
+}
+
+/// A modal being submitted with these `(custom_id, value)` inputs.
+pub fn modal(custom_id: &str, inputs: &[(&str, &str)]) -> Value {
+    let rows = inputs
+        .iter()
+        .map(|(id, value)| json!({ "type": 1, "components": [{ "type": 4, "custom_id": id, "value": value }] }))
+        .collect::<Vec<_>>();
+    interaction(5, json!({ "custom_id": custom_id, "components": rows }))
+}
+
+/// Make the message `interaction` came from show the nub at `url`.
+pub fn showing(mut interaction: Value, url: &str) -> Value {
+    if interaction["message"].is_null() {
+        interaction["message"] = message("");
+    }
+    interaction["message"]["embeds"] = json!([{ "type": "rich", "url": url }]);
+    interaction
+}
+
+/// A string option.
+pub fn string(name: &str, value: &str) -> Value {
+    json!({ "name": name, "type": 3, "value": value })
+}
+
+/// Give the member of `interaction` these permissions.
+pub fn with_permissions(mut interaction: Value, permissions: u64) -> Value {
+    interaction["member"]["permissions"] = json!(permissions.to_string());
+    interaction
+}
+
+pub fn user(id: &str) -> Value {
+    json!({ "id": id, "username": "nubfan", "discriminator": "0", "avatar": null, "global_name": null })
+}
+
+/// A message in the test channel, as a context menu command resolves it.
+pub fn message(content: &str) -> Value {
+    json!({
+        "id": MESSAGE_ID,
+        "channel_id": CHANNEL_ID,
+        "author": user("501"),
+        "content": content,
+        "timestamp": "2024-01-01T00:00:00.000000+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    })
+}
+
+/// A small solid PNG, for the nubs and images downloaded from the mock.
+pub fn png() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    RgbaImage::from_pixel(64, 48, Rgba([255, 128, 0, 255]))
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .unwrap();
+    bytes
+}
@@ -0,0 +1,59 @@
+use std::io::{ BufReader, Cursor };
+
+use crate::recorder::{ self, Entry };
+
+use super::*;
+
+/// A recording without the response statuses, which the mock doesn't mimic.
+fn without_status(entries: Vec<Entry>) -> Vec<Entry> {
+    entries
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Response { method, path, body, files, .. } => Entry::Response { method, path, status: 0, body, files },
+            interaction => interaction,
+        })
+        .collect()
+}
+
+/// Every recording in `src/tests/recordings/` gets the same responses when
+/// replayed against the fixture catalog.
+#[tokio::test]
+async fn recordings_replay_the_same() {
+    let dir = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/recordings"));
+    for path in std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()) {
+        let recording = std::fs::read_to_string(&path).unwrap();
+        let recorded = recording
+            .lines()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        let harness = Harness::new().await;
+        let replayed = recorder::replay(Cursor::new(&recording), &harness.discord, harness.state.clone()).await.unwrap();
+        assert_eq!(without_status(replayed), without_status(recorded), "{} replays differently", path.display());
+    }
+}
+
+#[tokio::test]
+async fn replay_skips_responses_and_reports_bad_lines() {
+    let harness = Harness::new().await;
+    let mut hello = command("hello", json!([]));
+    hello["token"] = json!("redacted-200");
+    let recording = format!(
+        "{}\n\n{}\n",
+        json!({ "kind": "interaction", "interaction": hello }),
+        json!({ "kind": "response", "method": "POST", "path": "x", "status": 500, "body": null, "files": [] })
+    );
+
+    let replayed = recorder::replay(BufReader::new(recording.as_bytes()), &harness.discord, harness.state.clone()).await.unwrap();
+    assert_eq!(replayed.len(), 2);
+    let Entry::Response { path, body, .. } = &replayed[1] else {
+        panic!("expected a response, got {:?}", replayed[1]);
+    };
+    assert_eq!(path, "interactions/200/redacted-200/callback");
+    assert_eq!(body["data"]["content"], crate::HelloCommand::URL);
+
+    let error = recorder::replay(Cursor::new("{\"kind\":\"interaction\"}\nnot json\n"), &harness.discord, harness.state.clone())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().starts_with("line 1:"), "{}", error);
+}
@@ -0,0 +1,202 @@
+//! The `/four` variants, loaded from a data file.
+//!
+//! `four_variants.json` is bundled; set `FOUR_VARIANTS` to a file with the
+//! same layout to use other ones. Each variant sends either a URL or a file,
+//! and when nobody picks one, a variant is drawn at random by weight among the
+//! ones rated for where it's going. There has to be a safe variant with some
+//! weight, so one can be drawn anywhere.
+
+use std::error::Error;
+
+use rand::seq::IndexedRandom;
+use twilight_model::{
+    application::command::{ Command, CommandOptionChoice, CommandOptionChoiceValue },
+    http::{ attachment::Attachment, interaction::InteractionResponseData },
+};
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::rating::ContentRating;
+
+const FOUR_VARIANTS_JSON: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/four_variants.json"));
+
+/// Files variants can send without them being on disk.
+const BUNDLED: &[(&str, &[u8])] = &[
+    ("four.mp3", include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/four.mp3"))),
+];
+
+/// Discord allows this many choices on an option.
+const MAX_VARIANTS: usize = 25;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Variant {
+    /// Shown in the command's choices.
+    pub name: String,
+    /// Stored in settings and stats, like `silly-nub`.
+    pub value: String,
+    /// Sent as the message content.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Name of a bundled file, or a path to one, sent as an attachment.
+    #[serde(default)]
+    pub attachment: Option<String>,
+    /// How likely the variant is to be the random default. 0 never is.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// `safe` unless given.
+    #[serde(default)]
+    pub rating: ContentRating,
+    #[serde(skip)]
+    bytes: Option<Vec<u8>>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Variant {
+    /// The response sending this four, optionally prefixed with a reply line.
+    pub fn response(&self, reply: Option<&str>) -> InteractionResponseData {
+        let content = match (reply, &self.url) {
+            (Some(reply), Some(url)) => Some(format!("{}\n{}", reply, url)),
+            (Some(reply), None) => Some(reply.to_string()),
+            (None, url) => url.clone(),
+        };
+
+        let mut builder = InteractionResponseDataBuilder::new();
+        if let Some(content) = content {
+            builder = builder.content(content);
+        }
+        if let Some(attachment) = self.attachment_file() {
+            builder = builder.attachments([attachment]);
+        }
+        builder.build()
+    }
+
+    pub fn attachment_file(&self) -> Option<Attachment> {
+        let name = self.attachment.as_deref()?;
+        let filename = name.rsplit(['/', '\\']).next().unwrap_or(name).to_string();
+        Some(Attachment::from_bytes(filename, self.bytes.clone()?, 1))
+    }
+}
+
+pub struct Variants {
+    variants: Vec<Variant>,
+}
+
+impl Variants {
+    pub fn load() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        match dotenvy::var("FOUR_VARIANTS") {
+            Ok(path) => Self::parse(&std::fs::read_to_string(path)?),
+            Err(_) => Self::parse(FOUR_VARIANTS_JSON),
+        }
+    }
+
+    fn parse(json: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut variants = serde_json::from_str::<Vec<Variant>>(json)?;
+
+        if variants.is_empty() || variants.len() > MAX_VARIANTS {
+            return Err(format!("there must be 1 to {} four variants", MAX_VARIANTS).into());
+        }
+        for i in 0..variants.len() {
+            if variants[..i].iter().any(|other| other.value == variants[i].value) {
+                return Err(format!("four variant `{}` is defined twice", variants[i].value).into());
+            }
+            let variant = &mut variants[i];
+            if variant.url.is_none() && variant.attachment.is_none() {
+                return Err(format!("four variant `{}` needs a url or an attachment", variant.value).into());
+            }
+            if variant.name.is_empty() || variant.name.chars().count() > 100 {
+                return Err(format!("four variant `{}` needs a name of up to 100 characters", variant.value).into());
+            }
+            if let Some(attachment) = &variant.attachment {
+                variant.bytes = Some(match BUNDLED.iter().find(|(name, _)| name == attachment) {
+                    Some((_, bytes)) => bytes.to_vec(),
+                    None => std::fs::read(attachment)?,
+                });
+            }
+        }
+        if !variants.iter().any(|variant| variant.rating == ContentRating::Safe && variant.weight > 0) {
+            return Err("at least one safe four variant needs a weight above 0".into());
+        }
+
+        Ok(Self { variants })
+    }
+
+    pub fn get(&self, value: &str) -> Option<&Variant> {
+        self.variants.iter().find(|variant| variant.value == value)
+    }
+
+    /// A variant rated at most `max_rating`, picked at random by weight.
+    pub fn random(&self, max_rating: ContentRating) -> &Variant {
+        self.variants
+            .choose_weighted(&mut rand::rng(), |variant| {
+                if variant.rating <= max_rating { variant.weight } else { 0 }
+            })
+            .expect("loading checks there's a safe variant with some weight")
+    }
+
+    /// Choices for the variants rated at most `max_rating` whose names
+    /// contain `filter`.
+    pub fn choices(&self, max_rating: ContentRating, filter: &str) -> Vec<CommandOptionChoice> {
+        let filter = filter.to_lowercase();
+        self.variants
+            .iter()
+            .filter(|variant| variant.rating <= max_rating && variant.name.to_lowercase().contains(&filter))
+            .map(|variant| CommandOptionChoice {
+                name: variant.name.clone(),
+                name_localizations: None,
+                value: CommandOptionChoiceValue::String(variant.value.clone()),
+            })
+            .collect()
+    }
+
+    /// Offer the variants rated at most `max_rating` as choices on the option
+    /// named `option`, found in `command` or under its subcommand `subcommand`.
+    pub fn fill_choices(
+        &self,
+        command: &mut Command,
+        subcommand: Option<&str>,
+        option: &str,
+        max_rating: ContentRating
+    ) {
+        let options = match subcommand {
+            Some(subcommand) => command.options
+                .iter_mut()
+                .find(|candidate| candidate.name == subcommand)
+                .and_then(|subcommand| subcommand.options.as_mut()),
+            None => Some(&mut command.options),
+        };
+        if let Some(option) = options.into_iter().flatten().find(|candidate| candidate.name == option) {
+            option.choices = Some(self.choices(max_rating, ""));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_a_safe_variant_to_draw() {
+        let edgy_only = r#"[
+            { "name": "a", "value": "a", "url": "https://example.com/a.gif", "rating": "edgy" },
+            { "name": "b", "value": "b", "url": "https://example.com/b.gif", "weight": 0 }
+        ]"#;
+        assert!(Variants::parse(edgy_only).is_err());
+        assert!(Variants::load().is_ok());
+    }
+
+    #[test]
+    fn draws_within_the_rating_by_weight() {
+        let variants = Variants::parse(r#"[
+            { "name": "safe", "value": "safe", "url": "https://example.com/safe.gif" },
+            { "name": "never", "value": "never", "url": "https://example.com/never.gif", "weight": 0 },
+            { "name": "edgy", "value": "edgy", "url": "https://example.com/edgy.gif", "weight": 1000, "rating": "edgy" }
+        ]"#).unwrap();
+
+        for _ in 0..100 {
+            assert_eq!(variants.random(ContentRating::Safe).value, "safe");
+            assert_ne!(variants.random(ContentRating::Nsfw).value, "never");
+        }
+    }
+}